@@ -0,0 +1,478 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flutter_rust_bridge::frb;
+
+use super::{
+    derivation, electrum,
+    filter::PoolFilter,
+    joinstr::{
+        join_coinjoin, list_mnemonic_coins, list_pools_filtered, Address, Coin, EmptyResult,
+        Mnemonic, Network, PeerConfig, Pool,
+    },
+    store,
+};
+
+/// How far back (in seconds) we look for pools.
+const POOLS_BACK: u64 = 600;
+/// How long (in seconds) we listen to the relay when listing pools.
+const POOLS_TIMEOUT: u64 = 10;
+/// Shortest delay (in seconds) between two lookups for pools.
+const MIN_INTERVAL: u64 = 10;
+
+static AUTOMIX: Mutex<Option<Arc<Engine>>> = Mutex::new(None);
+
+pub struct AutoMixConfig {
+    pub mnemonics: Mnemonic,
+    pub electrum_url: String,
+    pub electrum_port: u16,
+    pub relay: String,
+    pub network: Network,
+    /// Range of derivation indexes to look for coins in.
+    pub range: (u32, u32),
+    /// Addresses receiving the coinjoin outputs, used in order. If empty,
+    /// fresh receive addresses are derived from the mnemonic.
+    pub outputs: Vec<Address>,
+    /// Coins are remixed until they reach this mix depth.
+    pub target_depth: u32,
+    /// Maximum amount of fees (in sats) spent per day, the fees already
+    /// spent today are persisted across restarts.
+    pub max_daily_fee: u64,
+    /// Delay (in seconds) between two lookups for pools, at least
+    /// [`MIN_INTERVAL`].
+    pub interval: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoMixState {
+    Stopped,
+    Running,
+    Paused,
+    /// All eligible coins reached the target depth.
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct AutoMixStatus {
+    pub state: AutoMixState,
+    pub mixes: u32,
+    pub fees_today: u64,
+    pub last_txid: Option<String>,
+    pub last_error: Option<String>,
+}
+
+struct Engine {
+    config: AutoMixConfig,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+    /// Whether the thread runs, only changed with `status` locked so the
+    /// state of a finished engine is never overwritten.
+    alive: AtomicBool,
+    status: Mutex<AutoMixStatus>,
+    /// (day, fees spent during this day)
+    fees: Mutex<(u64, u64)>,
+    next_output: Mutex<usize>,
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("after epoch")
+        .as_secs()
+        / 86_400
+}
+
+/// Fees spent by automix today, as persisted.
+fn fees_today() -> u64 {
+    store::load()
+        .ok()
+        .map(|s| s.automix_fees)
+        .filter(|(day, _)| *day == today())
+        .map(|(_, fees)| fees)
+        .unwrap_or(0)
+}
+
+impl Engine {
+    fn new(config: AutoMixConfig) -> Self {
+        let fees = fees_today();
+        Engine {
+            config,
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            alive: AtomicBool::new(true),
+            status: Mutex::new(AutoMixStatus {
+                state: AutoMixState::Running,
+                mixes: 0,
+                fees_today: fees,
+                last_txid: None,
+                last_error: None,
+            }),
+            fees: Mutex::new((today(), fees)),
+            next_output: Mutex::new(0),
+        }
+    }
+
+    /// Switch between running and paused, ignored once the thread exited.
+    fn set_paused(&self, paused: bool) {
+        let mut status = self.status.lock().expect("poisoned");
+        if !self.alive.load(Ordering::SeqCst) {
+            return;
+        }
+        self.paused.store(paused, Ordering::SeqCst);
+        status.state = if paused {
+            AutoMixState::Paused
+        } else {
+            AutoMixState::Running
+        };
+    }
+
+    /// Record the final state, the engine can then be started again.
+    fn finish(&self, state: AutoMixState) {
+        let mut status = self.status.lock().expect("poisoned");
+        status.state = state;
+        self.alive.store(false, Ordering::SeqCst);
+    }
+
+    fn set_error(&self, error: String) {
+        log::warn!("automix: {error}");
+        self.status.lock().expect("poisoned").last_error = Some(error);
+    }
+
+    /// Fees we can still spend today.
+    fn fee_budget(&self) -> u64 {
+        let mut fees = self.fees.lock().expect("poisoned");
+        if fees.0 != today() {
+            *fees = (today(), 0);
+        }
+        self.config.max_daily_fee.saturating_sub(fees.1)
+    }
+
+    fn spend_fee(&self, fee: u64) {
+        let mut fees = self.fees.lock().expect("poisoned");
+        fees.1 += fee;
+        self.status.lock().expect("poisoned").fees_today = fees.1;
+        let spent = *fees;
+        if let Err(e) = store::update(|s| s.automix_fees = spent) {
+            log::warn!("fail to persist automix fees: {e}");
+        }
+    }
+
+    fn coins(&self) -> Result<Vec<Coin>, String> {
+        let res = list_mnemonic_coins(
+            &self.config.mnemonics,
+            self.config.electrum_url.clone(),
+            self.config.electrum_port,
+            self.config.range,
+            self.config.network,
+        );
+        match res.result() {
+            Some(coins) => Ok(coins
+                .into_iter()
+                .filter(|c| !c.is_frozen() && c.mix_depth() < self.config.target_depth)
+                .collect()),
+            None => Err(res.error().unwrap_or_default()),
+        }
+    }
+
+    fn pools(&self) -> Result<Vec<Pool>, String> {
+        let filter = PoolFilter {
+            network: Some(self.config.network),
+            only_joinable: true,
+            ..Default::default()
+        };
+        let res = list_pools_filtered(POOLS_BACK, POOLS_TIMEOUT, self.config.relay.clone(), filter);
+        match res.result() {
+            Some(pools) => Ok(pools),
+            None => Err(res.error().unwrap_or_default()),
+        }
+    }
+
+    /// Select a (coin, pool) pair, the fee paid by a peer is the difference
+    /// between its input and the pool denomination.
+    fn select(&self, coins: &[Coin], pools: &[Pool]) -> Option<(Coin, Pool, u64)> {
+        let budget = self.fee_budget();
+        let mut best: Option<(Coin, Pool, u64)> = None;
+        for coin in coins {
+            for pool in pools {
+                let Some(denomination) = pool.denomination_sat() else {
+                    continue;
+                };
+                if coin.amount_sat() < denomination {
+                    continue;
+                }
+                let fee = coin.amount_sat() - denomination;
+                if fee > budget {
+                    continue;
+                }
+                if best.as_ref().map(|b| fee < b.2).unwrap_or(true) {
+                    best = Some((coin.clone(), pool.clone(), fee));
+                }
+            }
+        }
+        best
+    }
+
+    fn outputs_left(&self) -> bool {
+        self.config.outputs.is_empty()
+            || *self.next_output.lock().expect("poisoned") < self.config.outputs.len()
+    }
+
+    /// The output of the next coinjoin, with its derivation index if it is
+    /// derived from the mnemonic. A derived address is only reserved once
+    /// the coinjoin succeeded, a failed round reuses it. It must stay within
+    /// `range` for the coinjoin output to be found again.
+    fn next_output(&self) -> Option<(Address, Option<u32>)> {
+        if self.config.outputs.is_empty() {
+            return electrum::Client::new(&self.config.electrum_url, self.config.electrum_port)
+                .and_then(|mut client| {
+                    derivation::peek_receive_address(
+                        &self.config.mnemonics,
+                        self.config.network,
+                        &mut client,
+                        Some(self.config.range.1),
+                    )
+                })
+                .map(|(index, address)| (address, Some(index)))
+                .map_err(|e| self.set_error(e))
+                .ok();
+        }
+        let mut next = self.next_output.lock().expect("poisoned");
+        let output = self.config.outputs.get(*next).cloned();
+        *next += 1;
+        output.map(|address| (address, None))
+    }
+
+    /// Run one mixing round, returns false if there is nothing left to do.
+    fn round(&self) -> Result<bool, String> {
+        let coins = self.coins()?;
+        if coins.is_empty() {
+            return Ok(false);
+        }
+        let pools = self.pools()?;
+        let Some((coin, pool, fee)) = self.select(&coins, &pools) else {
+            return Ok(true);
+        };
+        let Some((output, index)) = self.next_output() else {
+            return Ok(true);
+        };
+
+        let peer = PeerConfig {
+            mnemonics: self.config.mnemonics.share(),
+            electrum_url: self.config.electrum_url.clone(),
+            electrum_port: self.config.electrum_port,
+            input: coin,
+            output,
+            relay: self.config.relay.clone(),
+            ignore_verification: false,
+        };
+        let res = join_coinjoin(pool, peer);
+        match res.result() {
+            Some(txid) => {
+                self.spend_fee(fee);
+                if let Some(index) = index {
+                    // the address now has a history, it would be skipped anyway
+                    if let Err(e) = derivation::reserve_receive_address(
+                        &self.config.mnemonics,
+                        self.config.network,
+                        index,
+                    ) {
+                        self.set_error(e);
+                    }
+                }
+                let mut status = self.status.lock().expect("poisoned");
+                status.mixes += 1;
+                status.last_txid = Some(txid);
+                Ok(true)
+            }
+            None => Err(res.error().unwrap_or_default()),
+        }
+    }
+
+    fn run(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            if !self.outputs_left() {
+                self.set_error("no output address left".to_string());
+                break;
+            }
+            if !self.paused.load(Ordering::SeqCst) {
+                match self.round() {
+                    Ok(false) => {
+                        self.finish(AutoMixState::Done);
+                        return;
+                    }
+                    Ok(true) => {}
+                    Err(e) => self.set_error(e),
+                }
+            }
+            // sleep by small steps to stay responsive to pause/stop
+            for _ in 0..self.config.interval {
+                if self.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+        self.finish(AutoMixState::Stopped);
+    }
+}
+
+fn engine() -> Option<Arc<Engine>> {
+    AUTOMIX.lock().expect("poisoned").clone()
+}
+
+/// Start the auto-mix engine in a background thread, it keeps joining pools
+/// until all non-frozen coins reach `config.target_depth`.
+#[frb(sync)]
+pub fn automix_start(config: AutoMixConfig) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    if config.interval < MIN_INTERVAL {
+        res.set_error(format!("interval must be at least {MIN_INTERVAL} seconds"));
+        return res;
+    }
+    let mut automix = AUTOMIX.lock().expect("poisoned");
+    if let Some(engine) = automix.as_ref() {
+        if engine.alive.load(Ordering::SeqCst) {
+            res.set_error("automix already running".to_string());
+            return res;
+        }
+    }
+    let engine = Arc::new(Engine::new(config));
+    *automix = Some(engine.clone());
+    thread::spawn(move || engine.run());
+
+    res
+}
+
+#[frb(sync)]
+pub fn automix_pause() {
+    if let Some(engine) = engine() {
+        engine.set_paused(true);
+    }
+}
+
+#[frb(sync)]
+pub fn automix_resume() {
+    if let Some(engine) = engine() {
+        engine.set_paused(false);
+    }
+}
+
+/// Stop the engine, a coinjoin in progress is not interrupted.
+#[frb(sync)]
+pub fn automix_stop() {
+    if let Some(engine) = engine() {
+        engine.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[frb(sync)]
+pub fn automix_status() -> AutoMixStatus {
+    match engine() {
+        Some(engine) => engine.status.lock().expect("poisoned").clone(),
+        None => AutoMixStatus {
+            state: AutoMixState::Stopped,
+            mixes: 0,
+            fees_today: 0,
+            last_txid: None,
+            last_error: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{coin, data_dir, mnemonic, pool, script, MockElectrum};
+
+    fn config(max_daily_fee: u64, interval: u64) -> AutoMixConfig {
+        AutoMixConfig {
+            mnemonics: mnemonic(),
+            electrum_url: String::new(),
+            electrum_port: 0,
+            relay: String::new(),
+            network: Network::Regtest,
+            range: (0, 20),
+            outputs: Vec::new(),
+            target_depth: 2,
+            max_daily_fee,
+            interval,
+        }
+    }
+
+    #[test]
+    fn select_cheapest_pool_within_budget() {
+        let _dir = data_dir();
+        let engine = Engine::new(config(6_000, MIN_INTERVAL));
+        let coins = vec![coin(
+            OutPoint::new(Txid::all_zeros(), 0),
+            script(1),
+            110_000,
+        )];
+        let pools = vec![
+            // fee above the daily budget
+            pool("a", 100_000, 2, 1, u64::MAX),
+            pool("b", 104_000, 2, 1, u64::MAX),
+            pool("c", 105_000, 2, 1, u64::MAX),
+            // denomination above the coin value
+            pool("d", 120_000, 2, 1, u64::MAX),
+        ];
+        let (_, pool, fee) = engine.select(&coins, &pools).unwrap();
+        assert_eq!(pool.id(), "c");
+        assert_eq!(fee, 5_000);
+
+        assert!(engine.select(&coins, &pools[..1]).is_none());
+    }
+
+    #[test]
+    fn daily_fees_persisted() {
+        let _dir = data_dir();
+        let engine = Engine::new(config(5_000, MIN_INTERVAL));
+        engine.spend_fee(3_000);
+        assert_eq!(engine.fee_budget(), 2_000);
+
+        // a restarted engine knows what was spent today
+        let engine = Engine::new(config(5_000, MIN_INTERVAL));
+        assert_eq!(engine.status.lock().unwrap().fees_today, 3_000);
+        assert_eq!(engine.fee_budget(), 2_000);
+    }
+
+    #[test]
+    fn derived_output_reused_until_mixed() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let engine = Engine::new(AutoMixConfig {
+            electrum_url: electrum.url(),
+            electrum_port: electrum.port(),
+            range: (0, 2),
+            ..config(5_000, MIN_INTERVAL)
+        });
+        // a failed round does not consume the address
+        let (first, index) = engine.next_output().unwrap();
+        let (again, _) = engine.next_output().unwrap();
+        assert_eq!(index, Some(0));
+        assert_eq!(first.as_string(), again.as_string());
+
+        derivation::reserve_receive_address(&engine.config.mnemonics, Network::Regtest, 0).unwrap();
+        assert_eq!(engine.next_output().unwrap().1, Some(1));
+
+        // the next output would not be found in the coins range
+        derivation::reserve_receive_address(&engine.config.mnemonics, Network::Regtest, 1).unwrap();
+        assert!(engine.next_output().is_none());
+        assert!(engine.status.lock().unwrap().last_error.is_some());
+    }
+
+    #[test]
+    fn interval_too_short() {
+        let res = automix_start(config(5_000, MIN_INTERVAL - 1));
+        assert!(res.is_err());
+        assert_eq!(automix_status().state, AutoMixState::Stopped);
+    }
+}
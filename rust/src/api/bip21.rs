@@ -0,0 +1,134 @@
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::{Amount, Denomination};
+
+use super::{
+    joinstr::{Address, AddressType},
+    uri,
+};
+
+const SCHEME: &str = "bitcoin:";
+
+/// A BIP21 payment request.
+#[derive(Clone)]
+pub struct Bip21Uri {
+    pub address: Address,
+    pub amount_sat: Option<u64>,
+    pub label: Option<String>,
+    pub message: Option<String>,
+}
+
+/// Parse a BIP21 URI, unknown `req-` parameters make the URI invalid as
+/// required by the BIP.
+#[frb(sync)]
+pub fn parse_bip21(value: String) -> Option<Bip21Uri> {
+    let value = value.trim();
+    if value.len() < SCHEME.len() || !value[..SCHEME.len()].eq_ignore_ascii_case(SCHEME) {
+        return None;
+    }
+    let (address, query) = match value[SCHEME.len()..].split_once('?') {
+        Some((a, q)) => (a, Some(q)),
+        None => (&value[SCHEME.len()..], None),
+    };
+    let mut uri = Bip21Uri {
+        address: Address::from_string(address.to_string())?,
+        amount_sat: None,
+        label: None,
+        message: None,
+    };
+    for pair in query.into_iter().flat_map(|q| q.split('&')) {
+        let (key, value) = pair.split_once('=')?;
+        let value = uri::decode(value)?;
+        match key {
+            "amount" => {
+                let amount = Amount::from_str_in(&value, Denomination::Bitcoin).ok()?;
+                uri.amount_sat = Some(amount.to_sat());
+            }
+            "label" => uri.label = Some(value),
+            "message" => uri.message = Some(value),
+            k if k.starts_with("req-") => return None,
+            _ => {}
+        }
+    }
+    Some(uri)
+}
+
+impl Bip21Uri {
+    #[frb(sync)]
+    pub fn as_string(&self) -> String {
+        let mut uri = format!("{SCHEME}{}", self.address.as_string());
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount_sat {
+            let amount = Amount::from_sat(amount).to_string_in(Denomination::Bitcoin);
+            params.push(format!("amount={amount}"));
+        }
+        if let Some(label) = &self.label {
+            params.push(format!("label={}", uri::encode(label)));
+        }
+        if let Some(message) = &self.message {
+            params.push(format!("message={}", uri::encode(message)));
+        }
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        uri
+    }
+
+    /// Payload for a QR code: bech32 addresses are upper-cased so the QR
+    /// code can use the denser alphanumeric mode.
+    #[frb(sync)]
+    pub fn qr_payload(&self) -> String {
+        let address = self.address.as_string();
+        let bech32 = matches!(
+            self.address.address_type(),
+            AddressType::P2wpkh | AddressType::P2wsh | AddressType::P2tr
+        );
+        let address = if bech32 {
+            address.to_uppercase()
+        } else {
+            address
+        };
+        let uri = self.as_string();
+        let query = uri.split_once('?').map(|(_, q)| format!("?{q}"));
+        format!("BITCOIN:{address}{}", query.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+    #[test]
+    fn parse_all_fields() {
+        let uri = parse_bip21(format!(
+            "BITCOIN:{ADDRESS}?amount=0.0005&label=Luke%20Jr&message=tip&other=1"
+        ))
+        .unwrap();
+        assert_eq!(uri.address.as_string(), ADDRESS);
+        assert_eq!(uri.amount_sat, Some(50_000));
+        assert_eq!(uri.label.as_deref(), Some("Luke Jr"));
+        assert_eq!(uri.message.as_deref(), Some("tip"));
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(parse_bip21(ADDRESS.to_string()).is_none());
+        assert!(parse_bip21("bitcoin:notanaddress".to_string()).is_none());
+        assert!(parse_bip21(format!("bitcoin:{ADDRESS}?amount=abc")).is_none());
+        assert!(parse_bip21(format!("bitcoin:{ADDRESS}?req-unknown=1")).is_none());
+    }
+
+    #[test]
+    fn round_trip_and_qr() {
+        let uri = parse_bip21(format!("bitcoin:{ADDRESS}?amount=1.5&label=a%26b")).unwrap();
+        let string = uri.as_string();
+        assert_eq!(string, format!("bitcoin:{ADDRESS}?amount=1.5&label=a%26b"));
+        assert_eq!(parse_bip21(string).unwrap().amount_sat, Some(150_000_000));
+        assert_eq!(
+            uri.qr_payload(),
+            format!("BITCOIN:{}?amount=1.5&label=a%26b", ADDRESS.to_uppercase())
+        );
+    }
+}
@@ -0,0 +1,569 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flutter_rust_bridge::frb;
+use joinstr::interface;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    electrum,
+    joinstr::{
+        initiate_coinjoin, Address, Coin, EmptyResult, Mnemonic, Network, PeerConfig, PoolConfig,
+    },
+    labels, store,
+};
+
+/// Delay before announcing the pool again after a failed round, doubled
+/// after each failure up to [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Coordinators whose thread is running, by pool id.
+static RUNNING: Mutex<BTreeMap<u64, Coordinator>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CoordinatorState {
+    /// The pool is announced and waits for peers.
+    Running,
+    /// Cancelled while a round was announced: the joinstr core cannot
+    /// interrupt it, so the round still runs until it completes (the state
+    /// becomes `Done`) or fails (the state becomes `Cancelled`).
+    Cancelling,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+/// Everything needed to restart a pool after the app was killed, the
+/// mnemonic is not persisted and must be provided again on resume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CoordinatorRecord {
+    pub id: u64,
+    pub denomination: f64,
+    pub fee: u32,
+    pub peers: usize,
+    pub network: Network,
+    /// Unix timestamp after which the pool is not announced anymore.
+    pub deadline: u64,
+    pub electrum_url: String,
+    pub electrum_port: u16,
+    pub input: String,
+    pub output: String,
+    pub relay: String,
+    pub state: CoordinatorState,
+    pub txid: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoordinatorStatus {
+    pub state: CoordinatorState,
+    pub deadline: u64,
+    /// Number of peers the pool waits for. How many already registered is
+    /// not reported by the joinstr core.
+    pub pool_size: usize,
+    /// Rounds announced so far, a round is announced again when it fails.
+    pub rounds: u32,
+    pub txid: Option<String>,
+    pub error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("after epoch")
+        .as_secs()
+}
+
+/// A pool hosted in a background thread, its state is persisted so it can
+/// be resumed after a process restart.
+#[frb(opaque)]
+#[derive(Clone)]
+pub struct Coordinator {
+    #[frb(ignore)]
+    record: Arc<Mutex<CoordinatorRecord>>,
+    #[frb(ignore)]
+    cancelled: Arc<AtomicBool>,
+    #[frb(ignore)]
+    rounds: Arc<AtomicU32>,
+    /// Whether a round is announced by the core right now.
+    #[frb(ignore)]
+    in_round: Arc<AtomicBool>,
+}
+
+impl Coordinator {
+    /// Announce a new pool and run it in the background. As initiator our
+    /// input is signed without verifying the coinjoin, see `initiate_coinjoin`.
+    #[frb(sync)]
+    pub fn start(config: PoolConfig, peer: PeerConfig) -> CoordinatorResult {
+        let mut res = CoordinatorResult::new();
+        if labels::is_frozen(&peer.input.outpoint()) {
+            res.set_error("input coin is frozen".to_string());
+            return res;
+        }
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("after epoch")
+            .as_nanos() as u64;
+        let record = CoordinatorRecord {
+            id,
+            denomination: config.denomination,
+            fee: config.fee,
+            peers: config.peers,
+            network: config.network,
+            deadline: now() + config.max_duration,
+            electrum_url: peer.electrum_url.clone(),
+            electrum_port: peer.electrum_port,
+            input: peer.input.outpoint(),
+            output: peer.output.as_unchecked().assume_checked_ref().to_string(),
+            relay: peer.relay.clone(),
+            state: CoordinatorState::Running,
+            txid: None,
+            error: None,
+        };
+        let coordinator = Coordinator::new(record);
+        if let Err(e) = coordinator.persist() {
+            res.set_error(e);
+            return res;
+        }
+        let mut running = RUNNING.lock().expect("poisoned");
+        coordinator.spawn(&mut running, peer.mnemonics, peer.input, peer.output);
+        res.set(coordinator);
+
+        res
+    }
+
+    /// Resume the pools that were still running when the app stopped,
+    /// `coins` must contain the input coin of each pool. Pools already
+    /// running in this process are returned as is.
+    #[frb(sync)]
+    pub fn resume(mnemonics: &Mnemonic, coins: Vec<Coin>) -> Vec<Coordinator> {
+        let records = store::load().map(|s| s.coordinators).unwrap_or_default();
+        let mut running = RUNNING.lock().expect("poisoned");
+        let mut coordinators = Vec::new();
+        for record in records {
+            if record.state == CoordinatorState::Cancelling {
+                // the process stopped before the cancelled round ended
+                Coordinator::new(record).finish(Err("cancelled".to_string()));
+                continue;
+            }
+            if record.state != CoordinatorState::Running {
+                continue;
+            }
+            if let Some(coordinator) = running.get(&record.id) {
+                coordinators.push(coordinator.clone());
+                continue;
+            }
+            let coordinator = Coordinator::new(record.clone());
+            let input = coins.iter().find(|c| c.outpoint() == record.input);
+            let output = Address::from_string(record.output.clone());
+            match (input, output) {
+                (Some(input), Some(output)) if record.deadline > now() => {
+                    coordinator.spawn(&mut running, mnemonics.share(), input.clone(), output);
+                }
+                (None, _) => coordinator.finish(Err("input coin not found".to_string())),
+                (_, None) => coordinator.finish(Err("invalid output address".to_string())),
+                _ => coordinator.finish(Err("pool expired".to_string())),
+            }
+            coordinators.push(coordinator);
+        }
+        coordinators
+    }
+
+    #[frb(sync)]
+    pub fn status(&self) -> CoordinatorStatus {
+        let record = self.record.lock().expect("poisoned");
+        CoordinatorStatus {
+            state: record.state,
+            deadline: record.deadline,
+            pool_size: record.peers,
+            rounds: self.rounds.load(Ordering::SeqCst),
+            txid: record.txid.clone(),
+            error: record.error.clone(),
+        }
+    }
+
+    /// Push back the pool deadline by `seconds`, if the current round times
+    /// out the pool is announced again until the new deadline.
+    #[frb(sync)]
+    pub fn extend(&self, seconds: u64) -> EmptyResult {
+        let mut res = EmptyResult::new();
+        {
+            let mut record = self.record.lock().expect("poisoned");
+            if record.state != CoordinatorState::Running {
+                res.set_error("pool is not running".to_string());
+                return res;
+            }
+            record.deadline += seconds;
+        }
+        if let Err(e) = self.persist() {
+            res.set_error(e);
+        }
+
+        res
+    }
+
+    /// Stop announcing the pool. A round already announced cannot be
+    /// interrupted, the state is then `Cancelling` until the round ends.
+    #[frb(sync)]
+    pub fn cancel(&self) -> EmptyResult {
+        let mut res = EmptyResult::new();
+        // set before reading `in_round`, so `run` either sees it before
+        // announcing a round or the round is seen here
+        self.cancelled.store(true, Ordering::SeqCst);
+        {
+            let mut record = self.record.lock().expect("poisoned");
+            if record.state == CoordinatorState::Running {
+                record.state = if self.in_round.load(Ordering::SeqCst) {
+                    CoordinatorState::Cancelling
+                } else {
+                    CoordinatorState::Cancelled
+                };
+            }
+        }
+        if let Err(e) = self.persist() {
+            res.set_error(e);
+        }
+
+        res
+    }
+
+    fn new(record: CoordinatorRecord) -> Self {
+        Coordinator {
+            record: Arc::new(Mutex::new(record)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            rounds: Arc::new(AtomicU32::new(0)),
+            in_round: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn persist(&self) -> Result<(), String> {
+        let record = self.record.lock().expect("poisoned").clone();
+        store::update(|s| {
+            s.coordinators.retain(|r| r.id != record.id);
+            s.coordinators.push(record);
+        })
+    }
+
+    fn finish(&self, result: Result<String, String>) {
+        {
+            let mut record = self.record.lock().expect("poisoned");
+            match result {
+                Ok(txid) => {
+                    record.state = CoordinatorState::Done;
+                    record.txid = Some(txid);
+                }
+                Err(e) => {
+                    match record.state {
+                        CoordinatorState::Running => record.state = CoordinatorState::Failed,
+                        CoordinatorState::Cancelling => record.state = CoordinatorState::Cancelled,
+                        _ => {}
+                    }
+                    record.error = Some(e);
+                }
+            }
+        }
+        if let Err(e) = self.persist() {
+            log::warn!("fail to persist coordinator state: {e}");
+        }
+    }
+
+    /// Wait `delay` before the next round, returns false if the pool was
+    /// cancelled or expired meanwhile.
+    fn wait(&self, delay: Duration) -> bool {
+        let until = now() + delay.as_secs();
+        while now() < until {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return false;
+            }
+            if self.record.lock().expect("poisoned").deadline <= now() {
+                return false;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        true
+    }
+
+    /// Check that a failed round can be announced again: our input must
+    /// still be unspent. A server we cannot reach does not prevent a retry.
+    fn check_retriable(record: &CoordinatorRecord, input: &Coin) -> Result<(), String> {
+        let unspent = electrum::Client::new(&record.electrum_url, record.electrum_port)
+            .and_then(|mut client| client.script_unspent(&input.script_pubkey()));
+        match unspent {
+            Ok(unspent) if !unspent.iter().any(|(o, _)| *o == input.outpoint_inner()) => {
+                Err("input coin was spent".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the pool in a background thread, it is registered in `running`
+    /// until the thread exits.
+    fn spawn(
+        &self,
+        running: &mut BTreeMap<u64, Coordinator>,
+        mnemonics: Mnemonic,
+        input: Coin,
+        output: Address,
+    ) {
+        let id = self.record.lock().expect("poisoned").id;
+        running.insert(id, self.clone());
+        let coordinator = self.clone();
+        thread::spawn(move || {
+            coordinator.run(mnemonics, input, output);
+            RUNNING.lock().expect("poisoned").remove(&id);
+        });
+    }
+
+    fn run(&self, mnemonics: Mnemonic, input: Coin, output: Address) {
+        let peer = |record: &CoordinatorRecord| PeerConfig {
+            mnemonics: mnemonics.share(),
+            electrum_url: record.electrum_url.clone(),
+            electrum_port: record.electrum_port,
+            input: input.clone(),
+            output: output.clone(),
+            relay: record.relay.clone(),
+            // the initiator signs unverified, see `initiate_coinjoin`
+            ignore_verification: true,
+        };
+        // a peer config refused by the core would fail every round
+        let record = self.record.lock().expect("poisoned").clone();
+        if let Err(e) = interface::PeerConfig::try_from(peer(&record)) {
+            return self.finish(Err(e));
+        }
+        let mut delay = RETRY_DELAY;
+        loop {
+            let record = self.record.lock().expect("poisoned").clone();
+            let remaining = record.deadline.saturating_sub(now());
+            self.in_round.store(true, Ordering::SeqCst);
+            if self.cancelled.load(Ordering::SeqCst) {
+                self.in_round.store(false, Ordering::SeqCst);
+                return;
+            }
+            if remaining == 0 {
+                return self.finish(Err("pool expired".to_string()));
+            }
+            let config = PoolConfig {
+                denomination: record.denomination,
+                fee: record.fee,
+                max_duration: remaining,
+                peers: record.peers,
+                network: record.network,
+            };
+            self.rounds.fetch_add(1, Ordering::SeqCst);
+            let res = initiate_coinjoin(config, peer(&record));
+            self.in_round.store(false, Ordering::SeqCst);
+            if let Some(txid) = res.result() {
+                return self.finish(Ok(txid));
+            }
+            let error = res.error().unwrap_or_default();
+            if self.cancelled.load(Ordering::SeqCst) {
+                return self.finish(Err(error));
+            }
+            if let Err(e) = Self::check_retriable(&record, &input) {
+                return self.finish(Err(format!("{error}, {e}")));
+            }
+            log::info!("pool round failed, announce it again in {delay:?}: {error}");
+            if !self.wait(delay) {
+                if self.cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                return self.finish(Err(error));
+            }
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
+
+#[frb(opaque)]
+pub struct CoordinatorResult {
+    coordinator: Option<Coordinator>,
+    error: Option<String>,
+}
+
+impl CoordinatorResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            coordinator: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.coordinator.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.coordinator.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Coordinator> {
+        self.coordinator.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Coordinator) {
+        self.coordinator = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{self, hashes::Hash, OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{coin, data_dir, mnemonic, script};
+
+    fn record(id: u64, state: CoordinatorState, deadline: u64) -> CoordinatorRecord {
+        CoordinatorRecord {
+            id,
+            denomination: 0.001,
+            fee: 1,
+            peers: 5,
+            network: Network::Regtest,
+            deadline,
+            electrum_url: String::new(),
+            electrum_port: 0,
+            input: OutPoint::new(Txid::all_zeros(), id as u32).to_string(),
+            output: bitcoin::Address::from_script(&script(2), bitcoin::Network::Regtest)
+                .unwrap()
+                .to_string(),
+            relay: String::new(),
+            state,
+            txid: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn cancel_between_rounds() {
+        let _dir = data_dir();
+        let coordinator = Coordinator::new(record(1, CoordinatorState::Running, now() + 60));
+        assert!(coordinator.cancel().is_ok());
+        let status = coordinator.status();
+        assert_eq!(status.state, CoordinatorState::Cancelled);
+        assert_eq!(status.pool_size, 5);
+        assert!(coordinator.extend(60).is_err());
+    }
+
+    #[test]
+    fn cancel_during_round() {
+        let _dir = data_dir();
+        let failed = Coordinator::new(record(1, CoordinatorState::Running, now() + 60));
+        failed.in_round.store(true, Ordering::SeqCst);
+        assert!(failed.cancel().is_ok());
+        assert_eq!(failed.status().state, CoordinatorState::Cancelling);
+        failed.finish(Err("timeout".to_string()));
+        assert_eq!(failed.status().state, CoordinatorState::Cancelled);
+
+        // the core completed the round anyway
+        let done = Coordinator::new(record(2, CoordinatorState::Running, now() + 60));
+        done.in_round.store(true, Ordering::SeqCst);
+        assert!(done.cancel().is_ok());
+        done.finish(Ok("txid".to_string()));
+        let status = done.status();
+        assert_eq!(status.state, CoordinatorState::Done);
+        assert_eq!(status.txid.as_deref(), Some("txid"));
+    }
+
+    #[test]
+    fn resume_stale_pools() {
+        let _dir = data_dir();
+        let records = vec![
+            record(1, CoordinatorState::Running, now() + 60),
+            record(2, CoordinatorState::Running, now() - 1),
+            record(3, CoordinatorState::Cancelling, now() + 60),
+            record(4, CoordinatorState::Done, now() + 60),
+        ];
+        store::update(|s| s.coordinators = records).unwrap();
+
+        // the input of the first pool is missing, the second one expired
+        let coins = vec![coin(
+            OutPoint::new(Txid::all_zeros(), 2),
+            script(1),
+            100_000,
+        )];
+        let coordinators = Coordinator::resume(&mnemonic(), coins);
+        let errors: Vec<_> = coordinators.iter().map(|c| c.status().error).collect();
+        assert_eq!(
+            errors,
+            vec![
+                Some("input coin not found".to_string()),
+                Some("pool expired".to_string())
+            ]
+        );
+        assert!(coordinators
+            .iter()
+            .all(|c| c.status().state == CoordinatorState::Failed));
+
+        let states: Vec<_> = store::load()
+            .unwrap()
+            .coordinators
+            .iter()
+            .map(|r| (r.id, r.state))
+            .collect();
+        assert!(states.contains(&(3, CoordinatorState::Cancelled)));
+        assert!(states.contains(&(4, CoordinatorState::Done)));
+    }
+
+    #[test]
+    fn frozen_input_not_announced() {
+        let _dir = data_dir();
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+        assert!(labels::freeze_coin(outpoint.to_string(), true).is_ok());
+        let config = PoolConfig {
+            denomination: 0.001,
+            fee: 1,
+            max_duration: 60,
+            peers: 5,
+            network: Network::Regtest,
+        };
+        let peer = PeerConfig {
+            mnemonics: mnemonic(),
+            electrum_url: String::new(),
+            electrum_port: 0,
+            input: coin(outpoint, script(1), 100_500),
+            output: bitcoin::Address::from_script(&script(2), bitcoin::Network::Regtest)
+                .unwrap()
+                .into(),
+            relay: String::new(),
+            ignore_verification: false,
+        };
+        let res = Coordinator::start(config, peer);
+        assert_eq!(res.error().as_deref(), Some("input coin is frozen"));
+        assert!(store::load().unwrap().coordinators.is_empty());
+    }
+
+    #[test]
+    fn extend_persisted() {
+        let _dir = data_dir();
+        let deadline = now() + 60;
+        let coordinator = Coordinator::new(record(1, CoordinatorState::Running, deadline));
+        assert!(coordinator.extend(30).is_ok());
+        assert_eq!(coordinator.status().deadline, deadline + 30);
+        let records = store::load().unwrap().coordinators;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].deadline, deadline + 30);
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::BTreeSet;
+
+use flutter_rust_bridge::frb;
+use joinstr::{
+    miniscript::bitcoin::{
+        self,
+        bip32::{ChildNumber, DerivationPath, Xpriv, Xpub},
+        secp256k1::Secp256k1,
+        CompressedPublicKey, ScriptBuf,
+    },
+    signer,
+};
+
+use super::{
+    electrum,
+    joinstr::{Address, EmptyResult, Mnemonic, Network},
+    store,
+};
+
+/// Key of a wallet in the local state, the mnemonic itself is never
+/// persisted.
+fn wallet_key(mnemonics: &Mnemonic, network: Network) -> Result<String, String> {
+    let network: bitcoin::Network = network.into();
+    let seed = mnemonics.seed()?;
+    let xpriv = Xpriv::new_master(network, &*seed).map_err(|e| format!("{e}"))?;
+    let fingerprint = xpriv.fingerprint(&Secp256k1::new());
+    Ok(format!("{fingerprint}/{network}"))
+}
+
+/// BIP84 path of the key at `index` of `chain`: 0 for receive addresses,
+/// 1 for change, as in the depth of a coin path.
+pub(crate) fn bip84_path(network: bitcoin::Network, chain: u32, index: u32) -> DerivationPath {
+    let coin_type = if network == bitcoin::Network::Bitcoin {
+        0
+    } else {
+        1
+    };
+    [
+        ChildNumber::Hardened { index: 84 },
+        ChildNumber::Hardened { index: coin_type },
+        ChildNumber::Hardened { index: 0 },
+        ChildNumber::Normal { index: chain },
+        ChildNumber::Normal { index },
+    ]
+    .as_ref()
+    .into()
+}
+
+/// Scripts of the receive and change addresses of the wallet in `range`.
+pub(crate) fn wallet_scripts(
+    mnemonics: &Mnemonic,
+    network: Network,
+    range: (u32, u32),
+) -> Result<BTreeSet<ScriptBuf>, String> {
+    let network: bitcoin::Network = network.into();
+    let secp = Secp256k1::new();
+    let master = Xpriv::new_master(network, &*mnemonics.seed()?).map_err(|e| format!("{e}"))?;
+    let mut scripts = BTreeSet::new();
+    for chain in [0, 1] {
+        let account: DerivationPath = bip84_path(network, chain, 0)[..4].into();
+        let xpub = Xpub::from_priv(
+            &secp,
+            &master
+                .derive_priv(&secp, &account)
+                .map_err(|e| format!("{e}"))?,
+        );
+        for index in range.0..range.1 {
+            let key = xpub
+                .derive_pub(&secp, &[ChildNumber::Normal { index }])
+                .map_err(|e| format!("{e}"))?;
+            let pubkey = CompressedPublicKey(key.public_key);
+            scripts.insert(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()));
+        }
+    }
+    Ok(scripts)
+}
+
+/// Unused addresses a wallet looks past the last used one when it scans
+/// for coins, an address further than that would be missed on restore.
+pub(crate) const GAP_LIMIT: u32 = 20;
+
+fn used(client: &mut electrum::Client, address: &bitcoin::Address) -> Result<bool, String> {
+    Ok(!client.script_history(&address.script_pubkey())?.is_empty())
+}
+
+/// Find the next unused receive address of the wallet and its index,
+/// without reserving it: until [`reserve_receive_address`] is called the
+/// same address is returned again. Addresses that already have a history on
+/// `client` (e.g. handed out by another app using the same seed) are
+/// skipped. Fails if the index reaches `end` or lies more than
+/// [`GAP_LIMIT`] addresses past the last used one.
+pub(crate) fn peek_receive_address(
+    mnemonics: &Mnemonic,
+    network: Network,
+    client: &mut electrum::Client,
+    end: Option<u32>,
+) -> Result<(u32, Address), String> {
+    let key = wallet_key(mnemonics, network)?;
+    let signer = signer::WpkhHotSigner::new_from_mnemonics(network.into(), &mnemonics.phrase()?)
+        .map_err(|e| format!("{e}"))?;
+    let start = store::load()?
+        .address_indexes
+        .get(&key)
+        .copied()
+        .unwrap_or(0);
+    let mut index = start;
+    let address = loop {
+        let address = signer.recv_addr_at(index);
+        if !used(client, &address)? {
+            break address;
+        }
+        index += 1;
+    };
+    if let Some(end) = end.filter(|end| index >= *end) {
+        return Err(format!(
+            "receive index {index} is out of the scanned range, it ends at {end}"
+        ));
+    }
+    // an address skipped above is used and within the gap
+    if index == start && index >= GAP_LIMIT {
+        let mut within_gap = false;
+        for below in (index - GAP_LIMIT..index).rev() {
+            if used(client, &signer.recv_addr_at(below))? {
+                within_gap = true;
+                break;
+            }
+        }
+        if !within_gap {
+            return Err(format!(
+                "receive index {index} is past the gap limit of {GAP_LIMIT} unused addresses"
+            ));
+        }
+    }
+
+    Ok((index, address.into()))
+}
+
+/// Never derive the receive address at `index` again, nor the ones below.
+pub(crate) fn reserve_receive_address(
+    mnemonics: &Mnemonic,
+    network: Network,
+    index: u32,
+) -> Result<(), String> {
+    skip_receive_addresses(mnemonics, network, index + 1)
+}
+
+/// Derive the next unused receive address of the wallet, see
+/// [`peek_receive_address`]. Its index is reserved in the local state so
+/// the same address is never returned twice.
+pub(crate) fn next_receive_address(
+    mnemonics: &Mnemonic,
+    network: Network,
+    client: &mut electrum::Client,
+) -> Result<Address, String> {
+    let (index, address) = peek_receive_address(mnemonics, network, client, None)?;
+    reserve_receive_address(mnemonics, network, index)?;
+    Ok(address)
+}
+
+/// Mark all receive addresses below `index` as used, e.g. after restoring
+/// a wallet whose addresses were handed out by another app.
+pub(crate) fn skip_receive_addresses(
+    mnemonics: &Mnemonic,
+    network: Network,
+    index: u32,
+) -> Result<(), String> {
+    let key = wallet_key(mnemonics, network)?;
+    store::update(|s| {
+        let next = s.address_indexes.entry(key).or_insert(0);
+        *next = (*next).max(index);
+    })
+}
+
+/// Derive a fresh receive address from the wallet, suitable as a coinjoin
+/// output. The electrum server is asked to skip addresses already used.
+#[frb(sync)]
+pub fn new_receive_address(
+    mnemonics: &Mnemonic,
+    electrum_url: String,
+    electrum_port: u16,
+    network: Network,
+) -> AddressResult {
+    let mut res = AddressResult::new();
+    match electrum::Client::new(&electrum_url, electrum_port)
+        .and_then(|mut client| next_receive_address(mnemonics, network, &mut client))
+    {
+        Ok(address) => res.set(address),
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+/// Never derive receive addresses below `index` anymore.
+#[frb(sync)]
+pub fn set_receive_index(mnemonics: &Mnemonic, network: Network, index: u32) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    if let Err(e) = skip_receive_addresses(mnemonics, network, index) {
+        res.set_error(e);
+    }
+
+    res
+}
+
+#[frb(opaque)]
+pub struct AddressResult {
+    address: Option<Address>,
+    error: Option<String>,
+}
+
+impl AddressResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            address: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.address.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.address.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Address> {
+        self.address.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Address) {
+        self.address = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{data_dir, mnemonic, transaction, MockElectrum};
+
+    /// Give the receive address at `index` a history.
+    fn fund(electrum: &MockElectrum, index: u32) {
+        let signer = signer::WpkhHotSigner::new_from_mnemonics(
+            bitcoin::Network::Regtest,
+            &mnemonic().phrase().unwrap(),
+        )
+        .unwrap();
+        let script = signer.recv_addr_at(index).script_pubkey();
+        let outpoint = OutPoint::new(Txid::all_zeros(), index);
+        electrum.confirm(transaction(&[outpoint], &[(script, 10_000)]));
+    }
+
+    fn peek(electrum: &MockElectrum, end: Option<u32>) -> Result<u32, String> {
+        let mut client = electrum::Client::new(&electrum.url(), electrum.port())?;
+        peek_receive_address(&mnemonic(), Network::Regtest, &mut client, end).map(|(i, _)| i)
+    }
+
+    #[test]
+    fn change_scripts_included() {
+        let scripts = wallet_scripts(&mnemonic(), Network::Bitcoin, (0, 1)).unwrap();
+        assert_eq!(scripts.len(), 2);
+        for address in [
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el",
+        ] {
+            let address: bitcoin::Address<_> = address.parse().unwrap();
+            assert!(scripts.contains(&address.assume_checked().script_pubkey()));
+        }
+    }
+
+    #[test]
+    fn reserved_after_peek() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let mnemonics = mnemonic();
+        assert_eq!(peek(&electrum, None), Ok(0));
+        assert_eq!(peek(&electrum, None), Ok(0));
+
+        reserve_receive_address(&mnemonics, Network::Regtest, 0).unwrap();
+        assert_eq!(peek(&electrum, None), Ok(1));
+
+        // used addresses are skipped
+        fund(&electrum, 1);
+        fund(&electrum, 2);
+        let mut client = electrum::Client::new(&electrum.url(), electrum.port()).unwrap();
+        let first = next_receive_address(&mnemonics, Network::Regtest, &mut client).unwrap();
+        let second = next_receive_address(&mnemonics, Network::Regtest, &mut client).unwrap();
+        assert_ne!(first.as_string(), second.as_string());
+        assert_eq!(peek(&electrum, None), Ok(5));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        set_receive_index(&mnemonic(), Network::Regtest, 10);
+        assert_eq!(peek(&electrum, Some(11)), Ok(10));
+        assert!(peek(&electrum, Some(10)).is_err());
+    }
+
+    #[test]
+    fn gap_limit() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        set_receive_index(&mnemonic(), Network::Regtest, GAP_LIMIT);
+        assert!(peek(&electrum, None).is_err());
+
+        fund(&electrum, 0);
+        assert_eq!(peek(&electrum, None), Ok(GAP_LIMIT));
+        set_receive_index(&mnemonic(), Network::Regtest, GAP_LIMIT + 1);
+        assert!(peek(&electrum, None).is_err());
+    }
+
+    #[test]
+    fn receive_address_from_server() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let signer = signer::WpkhHotSigner::new_from_mnemonics(
+            bitcoin::Network::Regtest,
+            &mnemonic().phrase().unwrap(),
+        )
+        .unwrap();
+        fund(&electrum, 0);
+        let res = new_receive_address(
+            &mnemonic(),
+            electrum.url(),
+            electrum.port(),
+            Network::Regtest,
+        );
+        assert_eq!(
+            res.result().unwrap().as_string(),
+            signer.recv_addr_at(1).to_string()
+        );
+
+        let res = new_receive_address(&mnemonic(), electrum.url(), 1, Network::Regtest);
+        assert!(res.is_err());
+        // the failed call did not reserve anything
+        assert_eq!(peek(&electrum, None), Ok(2));
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::Arc,
+    time::Duration,
+};
+
+use joinstr::miniscript::bitcoin::{
+    block,
+    consensus::encode::{deserialize_hex, serialize_hex},
+    hashes::{sha256, Hash},
+    hex::DisplayHex,
+    OutPoint, Script, Transaction, Txid,
+};
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Electrum identifies scripts by the reversed sha256 of the script.
+pub(crate) fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hash.as_slice().to_lower_hex_string()
+}
+
+/// Convert a fee rate from BTC/kvb (as returned by the server) to sat/vb,
+/// rounded to 0.001 sat/vb so a rate of exactly 1 sat/vb does not become
+/// 1.0000000000000002 and round up to 2 when a whole rate is needed.
+fn sat_per_vb(rate: f64) -> f64 {
+    (rate * 100_000_000.0).round() / 1_000.0
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Tcp(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Minimal blocking electrum client, it only covers the few calls the
+/// joinstr core does not expose.
+pub(crate) struct Client {
+    stream: BufReader<Stream>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the server at `url`, over TLS if it starts with `ssl://`
+    /// (the certificate must chain to a webpki root, self-signed ones are
+    /// refused) and in plain TCP otherwise, `tcp://` is optional.
+    pub fn new(url: &str, port: u16) -> Result<Self, String> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::connect(url, port, roots)
+    }
+
+    fn connect(url: &str, port: u16, roots: RootCertStore) -> Result<Self, String> {
+        let (host, tls) = match url.strip_prefix("ssl://") {
+            Some(host) => (host, true),
+            None => (url.strip_prefix("tcp://").unwrap_or(url), false),
+        };
+        let stream = TcpStream::connect((host, port)).map_err(|e| format!("{e}"))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+        stream
+            .set_write_timeout(Some(TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+        let stream = if tls {
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .map_err(|e| format!("{e}"))?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            let name = ServerName::try_from(host.to_string()).map_err(|e| format!("{e}"))?;
+            let connection =
+                ClientConnection::new(Arc::new(config), name).map_err(|e| format!("{e}"))?;
+            Stream::Tls(Box::new(StreamOwned::new(connection, stream)))
+        } else {
+            Stream::Tcp(stream)
+        };
+        Ok(Self {
+            stream: BufReader::new(stream),
+            next_id: 0,
+        })
+    }
+
+    /// Send a request and wait for its response, notifications received in
+    /// the meantime are dropped.
+    pub fn call(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let mut line = request.to_string();
+        line.push('\n');
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(line.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|e| format!("{e}"))?;
+
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .map_err(|e| format!("{e}"))?;
+            if read == 0 {
+                return Err("electrum server closed the connection".to_string());
+            }
+            let response: Value = serde_json::from_str(&line).map_err(|e| format!("{e}"))?;
+            if response.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+            if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+                return Err(format!("electrum: {error}"));
+            }
+            return Ok(response.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+
+    pub fn get_transaction(&mut self, txid: &Txid) -> Result<Transaction, String> {
+        let raw = self.call("blockchain.transaction.get", json!([txid.to_string()]))?;
+        let raw = raw.as_str().ok_or("invalid transaction".to_string())?;
+        deserialize_hex(raw).map_err(|e| format!("{e}"))
+    }
+
+    /// Estimated fee rate (in sat/vb) to confirm within `target` blocks,
+    /// None if the server has not enough data.
+    pub fn estimate_fee(&mut self, target: u16) -> Result<Option<f64>, String> {
+        let rate = self.call("blockchain.estimatefee", json!([target]))?;
+        let rate = rate.as_f64().ok_or("invalid fee estimate".to_string())?;
+        // the server returns -1 if it cannot estimate
+        Ok((rate > 0.0).then(|| sat_per_vb(rate)))
+    }
+
+    /// Minimum fee rate (in sat/vb) accepted by the server mempool.
+    pub fn relay_fee(&mut self) -> Result<f64, String> {
+        let rate = self.call("blockchain.relayfee", json!([]))?;
+        let rate = rate.as_f64().ok_or("invalid relay fee".to_string())?;
+        Ok(sat_per_vb(rate))
+    }
+
+    /// Height of the chain tip, the server then notifies each new block.
+    pub fn tip_height(&mut self) -> Result<u32, String> {
+        let tip = self.call("blockchain.headers.subscribe", json!([]))?;
+        tip.get("height")
+            .and_then(Value::as_u64)
+            .map(|h| h as u32)
+            .ok_or("invalid tip".to_string())
+    }
+
+    pub fn block_header(&mut self, height: u32) -> Result<block::Header, String> {
+        let raw = self.call("blockchain.block.header", json!([height]))?;
+        let raw = raw.as_str().ok_or("invalid block header".to_string())?;
+        deserialize_hex(raw).map_err(|e| format!("{e}"))
+    }
+
+    /// Timestamp of the block at `height`.
+    pub fn block_time(&mut self, height: u32) -> Result<u32, String> {
+        Ok(self.block_header(height)?.time)
+    }
+
+    /// Negotiate the protocol version, returns the server software and the
+    /// protocol version it selected.
+    pub fn server_version(&mut self) -> Result<(String, String), String> {
+        let version = self.call("server.version", json!(["dart_joinstr", "1.4"]))?;
+        let field = |i: usize| {
+            version
+                .get(i)
+                .and_then(Value::as_str)
+                .map(String::from)
+                .ok_or("invalid server version".to_string())
+        };
+        Ok((field(0)?, field(1)?))
+    }
+
+    pub fn ping(&mut self) -> Result<(), String> {
+        self.call("server.ping", json!([])).map(|_| ())
+    }
+
+    /// Transactions touching `script` with their height, a height <= 0
+    /// means the transaction is in the mempool.
+    pub fn script_history(&mut self, script: &Script) -> Result<Vec<(Txid, i64)>, String> {
+        let history = self.call(
+            "blockchain.scripthash.get_history",
+            json!([script_hash(script)]),
+        )?;
+        let history = history.as_array().ok_or("invalid history".to_string())?;
+        history
+            .iter()
+            .map(|entry| {
+                let txid = entry
+                    .get("tx_hash")
+                    .and_then(Value::as_str)
+                    .and_then(|t| t.parse().ok())
+                    .ok_or("invalid history entry".to_string())?;
+                let height = entry
+                    .get("height")
+                    .and_then(Value::as_i64)
+                    .ok_or("invalid history entry".to_string())?;
+                Ok((txid, height))
+            })
+            .collect()
+    }
+
+    pub fn broadcast(&mut self, tx: &Transaction) -> Result<Txid, String> {
+        let txid = self.call(
+            "blockchain.transaction.broadcast",
+            json!([serialize_hex(tx)]),
+        )?;
+        txid.as_str()
+            .and_then(|t| t.parse().ok())
+            .ok_or("invalid broadcast response".to_string())
+    }
+
+    /// Unspent outputs paying to `script` with their height, a height <= 0
+    /// means the output is unconfirmed.
+    pub fn script_unspent(&mut self, script: &Script) -> Result<Vec<(OutPoint, i64)>, String> {
+        let unspent = self.call(
+            "blockchain.scripthash.listunspent",
+            json!([script_hash(script)]),
+        )?;
+        let unspent = unspent
+            .as_array()
+            .ok_or("invalid unspent list".to_string())?;
+        unspent
+            .iter()
+            .map(|entry| {
+                let txid: Txid = entry
+                    .get("tx_hash")
+                    .and_then(Value::as_str)
+                    .and_then(|t| t.parse().ok())
+                    .ok_or("invalid unspent entry".to_string())?;
+                let vout = entry
+                    .get("tx_pos")
+                    .and_then(Value::as_u64)
+                    .ok_or("invalid unspent entry".to_string())?;
+                let height = entry
+                    .get("height")
+                    .and_then(Value::as_i64)
+                    .ok_or("invalid unspent entry".to_string())?;
+                Ok((OutPoint::new(txid, vout as u32), height))
+            })
+            .collect()
+    }
+
+    /// Ask the server to notify each change of the history of `script`, see
+    /// [`Client::wait_notification`].
+    pub fn subscribe_script(&mut self, script: &Script) -> Result<(), String> {
+        self.call(
+            "blockchain.scripthash.subscribe",
+            json!([script_hash(script)]),
+        )?;
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for a notification of one of our subscriptions,
+    /// returns its method or `None` if nothing came.
+    pub fn wait_notification(&mut self, timeout: Duration) -> Result<Option<String>, String> {
+        self.stream
+            .get_ref()
+            .tcp()
+            .set_read_timeout(Some(timeout))
+            .map_err(|e| format!("{e}"))?;
+        let mut line = String::new();
+        let read = self.stream.read_line(&mut line);
+        self.stream
+            .get_ref()
+            .tcp()
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+        match read {
+            Ok(0) => Err("electrum server closed the connection".to_string()),
+            Ok(_) => {
+                let message: Value = serde_json::from_str(&line).map_err(|e| format!("{e}"))?;
+                Ok(message
+                    .get("method")
+                    .and_then(Value::as_str)
+                    .map(String::from))
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(format!("{e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use joinstr::miniscript::bitcoin::OutPoint;
+    use rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+        ServerConfig, ServerConnection,
+    };
+
+    use super::*;
+    use crate::test_support::{script, transaction, MockElectrum};
+
+    /// Serve one TLS connection answering `server.ping`, returns the port
+    /// and the self-signed certificate of the server.
+    fn tls_server() -> (u16, CertificateDer<'static>) {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let params = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        let cert = params.self_signed(&key).unwrap().der().clone();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.clone()],
+                    PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+                )
+                .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = BufReader::new(StreamOwned::new(connection, stream));
+            let mut line = String::new();
+            while stream.read_line(&mut line).unwrap_or(0) > 0 {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": null});
+                let stream = stream.get_mut();
+                writeln!(stream, "{response}").unwrap();
+                stream.flush().unwrap();
+                line.clear();
+            }
+        });
+        (port, cert)
+    }
+
+    #[test]
+    fn tls_connection() {
+        let (port, cert) = tls_server();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let mut client = Client::connect("ssl://localhost", port, roots).unwrap();
+        client.ping().unwrap();
+    }
+
+    #[test]
+    fn tls_certificate_checked() {
+        let (port, _) = tls_server();
+        let mut client = Client::new("ssl://localhost", port).unwrap();
+        assert!(client.ping().is_err());
+
+        // a plain TCP server does not speak TLS
+        let server = MockElectrum::start();
+        let mut client = Client::new("ssl://localhost", server.port()).unwrap();
+        assert!(client.ping().is_err());
+        let mut client = Client::new("tcp://127.0.0.1", server.port()).unwrap();
+        client.ping().unwrap();
+    }
+
+    #[test]
+    fn history_and_unspent() {
+        let server = MockElectrum::start();
+        let funding = transaction(&[OutPoint::null()], &[(script(1), 50_000)]);
+        let funding = server.confirm(funding);
+        let spending = transaction(&[OutPoint::new(funding, 0)], &[(script(2), 49_000)]);
+        let spending = server.add_mempool(spending);
+
+        let mut client = Client::new(&server.url(), server.port()).unwrap();
+        let mut history = client.script_history(&script(1)).unwrap();
+        history.sort_by_key(|(_, height)| -height);
+        assert_eq!(history, vec![(funding, 1), (spending, 0)]);
+
+        assert!(client.script_unspent(&script(1)).unwrap().is_empty());
+        assert_eq!(
+            client.script_unspent(&script(2)).unwrap(),
+            vec![(OutPoint::new(spending, 0), 0)]
+        );
+    }
+
+    #[test]
+    fn get_and_broadcast_transaction() {
+        let server = MockElectrum::start();
+        let tx = transaction(&[OutPoint::null()], &[(script(3), 10_000)]);
+
+        let mut client = Client::new(&server.url(), server.port()).unwrap();
+        let txid = client.broadcast(&tx).unwrap();
+        assert_eq!(txid, tx.compute_txid());
+        assert_eq!(server.broadcasted(), vec![tx.clone()]);
+        assert_eq!(client.get_transaction(&txid).unwrap(), tx);
+        assert!(client.get_transaction(&Txid::all_zeros()).is_err());
+    }
+
+    #[test]
+    fn script_notifications() {
+        let server = MockElectrum::start();
+        let mut client = Client::new(&server.url(), server.port()).unwrap();
+        client.subscribe_script(&script(4)).unwrap();
+        let wait = Duration::from_millis(200);
+        assert_eq!(client.wait_notification(wait).unwrap(), None);
+
+        let tx = server.add_mempool(transaction(&[OutPoint::null()], &[(script(4), 10_000)]));
+        assert_eq!(
+            client.wait_notification(wait).unwrap().as_deref(),
+            Some("blockchain.scripthash.subscribe")
+        );
+        // the connection still answers requests after a notification
+        assert_eq!(client.script_history(&script(4)).unwrap(), vec![(tx, 0)]);
+
+        // other scripts do not notify
+        server.add_mempool(transaction(&[OutPoint::new(tx, 1)], &[(script(5), 9_000)]));
+        assert_eq!(client.wait_notification(wait).unwrap(), None);
+    }
+
+    #[test]
+    fn headers() {
+        let server = MockElectrum::start();
+        server.mine(3);
+
+        let mut client = Client::new(&server.url(), server.port()).unwrap();
+        assert_eq!(client.tip_height().unwrap(), 3);
+        let genesis = client.block_header(0).unwrap();
+        assert_eq!(client.block_time(2).unwrap(), genesis.time + 1_200);
+        assert!(client.block_header(4).is_err());
+    }
+
+    #[test]
+    fn fee_estimates() {
+        let server = MockElectrum::start();
+        let mut client = Client::new(&server.url(), server.port()).unwrap();
+        assert_eq!(client.estimate_fee(1).unwrap(), None);
+
+        server.set_fee_estimate(Some(12.0));
+        let rate = client.estimate_fee(1).unwrap().unwrap();
+        assert!((rate - 12.0).abs() < 1e-6);
+        assert!((client.relay_fee().unwrap() - 1.0).abs() < 1e-6);
+    }
+}
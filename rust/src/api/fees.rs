@@ -0,0 +1,157 @@
+use flutter_rust_bridge::frb;
+
+use super::electrum;
+
+/// Confirmation targets (in blocks) we query estimates for.
+const TARGETS: [u16; 5] = [1, 3, 6, 12, 25];
+
+/// Virtual size of the coinjoin part paid by each peer: one P2WPKH input
+/// and one P2WPKH output.
+const PEER_VSIZE: f64 = 68.0 + 31.0;
+/// Virtual size of the transaction header, shared by all peers.
+const HEADER_VSIZE: f64 = 10.5;
+
+#[derive(Debug, Clone)]
+pub struct FeeSuggestion {
+    /// Confirmation target in blocks.
+    pub target: u16,
+    /// Fee rate estimated by the server in sat/vb.
+    pub fee_rate: f64,
+    /// Value to use as `PoolConfig.fee`.
+    pub pool_fee: u32,
+    /// Fee paid by each peer, in sats.
+    pub fee_per_peer_sat: u64,
+}
+
+fn suggestion(target: u16, fee_rate: f64, peers: usize) -> FeeSuggestion {
+    let pool_fee = fee_rate.ceil() as u32;
+    let peer_vsize = PEER_VSIZE + HEADER_VSIZE / peers.max(1) as f64;
+    FeeSuggestion {
+        target,
+        fee_rate,
+        pool_fee,
+        fee_per_peer_sat: (peer_vsize * pool_fee as f64).ceil() as u64,
+    }
+}
+
+/// Query the electrum server fee estimates and convert them to pool fees
+/// for a pool of `peers` peers, the targets the server cannot estimate are
+/// skipped.
+#[frb(sync)]
+pub fn estimate_pool_fees(
+    electrum_url: String,
+    electrum_port: u16,
+    peers: usize,
+) -> FeeSuggestionsResult {
+    let mut res = FeeSuggestionsResult::new();
+    let mut client = match electrum::Client::new(&electrum_url, electrum_port) {
+        Ok(c) => c,
+        Err(e) => {
+            res.set_error(e);
+            return res;
+        }
+    };
+    let relay_fee = match client.relay_fee() {
+        Ok(r) => r,
+        Err(e) => {
+            res.set_error(e);
+            return res;
+        }
+    };
+
+    let mut suggestions = Vec::new();
+    for target in TARGETS {
+        match client.estimate_fee(target) {
+            Ok(Some(rate)) => suggestions.push(suggestion(target, rate.max(relay_fee), peers)),
+            Ok(None) => {}
+            Err(e) => {
+                res.set_error(e);
+                return res;
+            }
+        }
+    }
+    res.set(suggestions);
+
+    res
+}
+
+#[frb(opaque)]
+pub struct FeeSuggestionsResult {
+    suggestions: Option<Vec<FeeSuggestion>>,
+    error: Option<String>,
+}
+
+impl FeeSuggestionsResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            suggestions: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.suggestions.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.suggestions.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Vec<FeeSuggestion>> {
+        self.suggestions.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Vec<FeeSuggestion>) {
+        self.suggestions = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockElectrum;
+
+    #[test]
+    fn suggestions_from_server_estimates() {
+        let server = MockElectrum::start();
+        server.set_fee_estimate(Some(5.5));
+        let res = estimate_pool_fees(server.url(), server.port(), 5);
+        let suggestions = res.result().unwrap();
+        assert_eq!(suggestions.len(), TARGETS.len());
+        for suggestion in suggestions {
+            assert_eq!(suggestion.pool_fee, 6);
+            // (68 + 31 + 10.5 / 5) * 6
+            assert_eq!(suggestion.fee_per_peer_sat, 607);
+        }
+    }
+
+    #[test]
+    fn relay_fee_is_a_floor() {
+        let server = MockElectrum::start();
+        server.set_fee_estimate(Some(0.2));
+        let res = estimate_pool_fees(server.url(), server.port(), 5);
+        assert!(res.result().unwrap().iter().all(|s| s.pool_fee == 1));
+    }
+
+    #[test]
+    fn no_estimate() {
+        let server = MockElectrum::start();
+        let res = estimate_pool_fees(server.url(), server.port(), 5);
+        assert!(res.result().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use joinstr::miniscript::bitcoin;
+
+use super::joinstr::{Network, Pool};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSort {
+    Denomination,
+    Fee,
+    Peers,
+}
+
+/// Criteria applied to the pools returned by `list_pools_filtered`, `None`
+/// fields are not checked.
+///
+/// A pool announcement does not say how many peers already registered nor
+/// which pools we host (the joinstr core announces them with a key it does
+/// not share), so full pools and our own pools cannot be filtered out.
+#[derive(Debug, Clone, Default)]
+pub struct PoolFilter {
+    pub network: Option<Network>,
+    pub min_denomination_sat: Option<u64>,
+    pub max_denomination_sat: Option<u64>,
+    pub max_fee: Option<u32>,
+    pub min_peers: Option<usize>,
+    pub max_peers: Option<usize>,
+    /// Drop pools we cannot join: without payload, with a fee we do not
+    /// support or past their timeout.
+    pub only_joinable: bool,
+    pub sort: Option<PoolSort>,
+    pub descending: bool,
+}
+
+fn in_range<T: PartialOrd>(value: Option<T>, min: Option<T>, max: Option<T>) -> bool {
+    match value {
+        Some(v) => min.map(|m| v >= m).unwrap_or(true) && max.map(|m| v <= m).unwrap_or(true),
+        None => min.is_none() && max.is_none(),
+    }
+}
+
+fn expired(pool: &Pool) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("after epoch")
+        .as_secs();
+    pool.timeout().map(|t| t <= now).unwrap_or(true)
+}
+
+impl PoolFilter {
+    fn accept(&self, pool: &Pool) -> bool {
+        if let Some(network) = self.network {
+            if pool.network_inner() != bitcoin::Network::from(network) {
+                return false;
+            }
+        }
+        if !in_range(
+            pool.denomination_sat(),
+            self.min_denomination_sat,
+            self.max_denomination_sat,
+        ) {
+            return false;
+        }
+        if !in_range(pool.fee(), None, self.max_fee) {
+            return false;
+        }
+        if !in_range(pool.peers(), self.min_peers, self.max_peers) {
+            return false;
+        }
+        if self.only_joinable
+            && (pool.denomination_sat().is_none()
+                || pool.fee().is_none()
+                || pool.peers().unwrap_or(0) < 2
+                || expired(pool))
+        {
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn apply(&self, pools: Vec<Pool>) -> Vec<Pool> {
+        let mut pools: Vec<_> = pools.into_iter().filter(|p| self.accept(p)).collect();
+        if let Some(sort) = self.sort {
+            pools.sort_by_key(|p| match sort {
+                PoolSort::Denomination => p.denomination_sat().unwrap_or(0),
+                PoolSort::Fee => p.fee().unwrap_or(0) as u64,
+                PoolSort::Peers => p.peers().unwrap_or(0) as u64,
+            });
+            if self.descending {
+                pools.reverse();
+            }
+        }
+        pools
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::pool;
+
+    fn later() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 600
+    }
+
+    fn ids(pools: &[Pool]) -> Vec<String> {
+        pools.iter().map(|p| p.id()).collect()
+    }
+
+    fn pools() -> Vec<Pool> {
+        vec![
+            pool("small", 10_000, 3, 1, later()),
+            pool("big", 1_000_000, 5, 4, later()),
+            pool("medium", 100_000, 4, 2, later()),
+        ]
+    }
+
+    #[test]
+    fn ranges() {
+        let filter = PoolFilter {
+            min_denomination_sat: Some(50_000),
+            max_fee: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(ids(&filter.apply(pools())), vec!["medium"]);
+
+        let filter = PoolFilter {
+            min_peers: Some(4),
+            max_peers: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(ids(&filter.apply(pools())), vec!["big", "medium"]);
+    }
+
+    #[test]
+    fn network() {
+        let filter = PoolFilter {
+            network: Some(Network::Bitcoin),
+            ..Default::default()
+        };
+        assert!(filter.apply(pools()).is_empty());
+        let filter = PoolFilter {
+            network: Some(Network::Regtest),
+            ..Default::default()
+        };
+        assert_eq!(filter.apply(pools()).len(), 3);
+    }
+
+    #[test]
+    fn only_joinable() {
+        let mut pools = pools();
+        pools.push(pool("expired", 10_000, 3, 1, later() - 1_200));
+        pools.push(pool("alone", 10_000, 1, 1, later()));
+        let filter = PoolFilter {
+            only_joinable: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&filter.apply(pools)), vec!["small", "big", "medium"]);
+    }
+
+    #[test]
+    fn sort() {
+        let filter = PoolFilter {
+            sort: Some(PoolSort::Denomination),
+            ..Default::default()
+        };
+        assert_eq!(ids(&filter.apply(pools())), vec!["small", "medium", "big"]);
+        let filter = PoolFilter {
+            sort: Some(PoolSort::Fee),
+            descending: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&filter.apply(pools())), vec!["big", "medium", "small"]);
+    }
+}
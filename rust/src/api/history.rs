@@ -0,0 +1,287 @@
+use std::collections::{btree_map::Entry, BTreeMap};
+
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::{OutPoint, Transaction, Txid};
+
+use super::{
+    derivation, electrum,
+    joinstr::{Mnemonic, Network},
+    mix, store,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalletTransaction {
+    pub txid: String,
+    pub direction: TxDirection,
+    /// Amount received minus amount spent by the wallet.
+    pub net_sat: i64,
+    /// None for incoming transactions, we do not fetch their inputs.
+    pub fee_sat: Option<u64>,
+    /// None if the transaction is unconfirmed.
+    pub height: Option<u32>,
+    /// Block timestamp, None if the transaction is unconfirmed.
+    pub timestamp: Option<u32>,
+    pub is_coinjoin: bool,
+}
+
+fn fetch(
+    client: &mut electrum::Client,
+    txs: &mut BTreeMap<Txid, Transaction>,
+    txid: Txid,
+) -> Result<(), String> {
+    if let Entry::Vacant(entry) = txs.entry(txid) {
+        entry.insert(client.get_transaction(&txid)?);
+    }
+    Ok(())
+}
+
+fn history(
+    mnemonics: &Mnemonic,
+    electrum_url: &str,
+    electrum_port: u16,
+    range: (u32, u32),
+    network: Network,
+) -> Result<Vec<WalletTransaction>, String> {
+    let scripts = derivation::wallet_scripts(mnemonics, network, range)?;
+
+    let mut client = electrum::Client::new(electrum_url, electrum_port)?;
+    let mut heights = BTreeMap::new();
+    for script in &scripts {
+        for (txid, height) in client.script_history(script)? {
+            heights.insert(txid, height);
+        }
+    }
+
+    // every transaction spending one of our outputs is in our history, so
+    // the previous transactions of our inputs are always fetched here
+    let mut txs = BTreeMap::new();
+    for txid in heights.keys() {
+        fetch(&mut client, &mut txs, *txid)?;
+    }
+    let coinjoins = store::load().map(|s| s.coinjoins).unwrap_or_default();
+    let prevout = |txs: &BTreeMap<Txid, Transaction>, outpoint: &OutPoint| {
+        txs.get(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize))
+            .cloned()
+    };
+
+    let mut times = BTreeMap::new();
+    let mut transactions = Vec::new();
+    for (txid, height) in heights {
+        let tx = txs[&txid].clone();
+        let received: u64 = tx
+            .output
+            .iter()
+            .filter(|o| scripts.contains(&o.script_pubkey))
+            .map(|o| o.value.to_sat())
+            .sum();
+        let spent: u64 = tx
+            .input
+            .iter()
+            .filter_map(|i| prevout(&txs, &i.previous_output))
+            .filter(|o| scripts.contains(&o.script_pubkey))
+            .map(|o| o.value.to_sat())
+            .sum();
+
+        let fee_sat = if spent > 0 {
+            for input in &tx.input {
+                fetch(&mut client, &mut txs, input.previous_output.txid)?;
+            }
+            let inputs: Option<u64> = tx
+                .input
+                .iter()
+                .map(|i| prevout(&txs, &i.previous_output).map(|o| o.value.to_sat()))
+                .sum();
+            let outputs: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+            inputs.and_then(|i| i.checked_sub(outputs))
+        } else {
+            None
+        };
+
+        let is_coinjoin = coinjoins.contains_key(&txid.to_string())
+            || tx.output.iter().enumerate().any(|(vout, o)| {
+                scripts.contains(&o.script_pubkey)
+                    && mix::is_coinjoin_output(&tx, &OutPoint::new(txid, vout as u32))
+            });
+
+        let height = (height > 0).then_some(height as u32);
+        let timestamp = match height {
+            Some(h) => match times.get(&h) {
+                Some(t) => Some(*t),
+                None => {
+                    let time = client.block_time(h)?;
+                    times.insert(h, time);
+                    Some(time)
+                }
+            },
+            None => None,
+        };
+
+        let net_sat = received as i64 - spent as i64;
+        transactions.push(WalletTransaction {
+            txid: txid.to_string(),
+            direction: if net_sat >= 0 {
+                TxDirection::Incoming
+            } else {
+                TxDirection::Outgoing
+            },
+            net_sat,
+            fee_sat,
+            height,
+            timestamp,
+            is_coinjoin,
+        });
+    }
+
+    // most recent first, unconfirmed on top
+    transactions.sort_by_key(|t| std::cmp::Reverse(t.height.unwrap_or(u32::MAX)));
+    Ok(transactions)
+}
+
+/// List the transactions touching the wallet receive and change addresses
+/// in `range`.
+#[frb(sync)]
+pub fn list_transactions(
+    mnemonics: &Mnemonic,
+    electrum_url: String,
+    electrum_port: u16,
+    range: (u32, u32),
+    network: Network,
+) -> ListTransactionsResult {
+    let mut res = ListTransactionsResult::new();
+    match history(mnemonics, &electrum_url, electrum_port, range, network) {
+        Ok(txs) => res.set(txs),
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+#[frb(opaque)]
+pub struct ListTransactionsResult {
+    transactions: Option<Vec<WalletTransaction>>,
+    error: Option<String>,
+}
+
+impl ListTransactionsResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            transactions: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.transactions.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.transactions.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Vec<WalletTransaction>> {
+        self.transactions.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Vec<WalletTransaction>) {
+        self.transactions = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, Address, ScriptBuf};
+
+    use super::*;
+    use crate::test_support::{data_dir, mnemonic, script, transaction, MockElectrum};
+
+    /// First receive and change addresses of the BIP84 test vectors.
+    fn wallet_script(address: &str) -> ScriptBuf {
+        address
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked()
+            .script_pubkey()
+    }
+
+    fn list(electrum: &MockElectrum) -> Vec<WalletTransaction> {
+        history(
+            &mnemonic(),
+            &electrum.url(),
+            electrum.port(),
+            (0, 1),
+            Network::Bitcoin,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn change_output_listed() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let receive = wallet_script("bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        let change = wallet_script("bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el");
+        let funding = electrum.confirm(transaction(
+            &[OutPoint::new(Txid::all_zeros(), 0)],
+            &[(receive, 10_000)],
+        ));
+        let spend = electrum.add_mempool(transaction(
+            &[OutPoint::new(funding, 0)],
+            &[(script(1), 6_000), (change.clone(), 3_000)],
+        ));
+        let change_only = electrum.confirm(transaction(
+            &[OutPoint::new(Txid::all_zeros(), 1)],
+            &[(change, 2_000)],
+        ));
+
+        let txs = list(&electrum);
+        assert_eq!(txs.len(), 3);
+        assert_eq!(txs[0].txid, spend.to_string());
+        assert_eq!(txs[0].direction, TxDirection::Outgoing);
+        assert_eq!(txs[0].net_sat, -7_000);
+        assert_eq!(txs[0].fee_sat, Some(1_000));
+        assert_eq!(txs[0].height, None);
+        let change_only = txs
+            .iter()
+            .find(|t| t.txid == change_only.to_string())
+            .unwrap();
+        assert_eq!(change_only.direction, TxDirection::Incoming);
+        assert_eq!(change_only.net_sat, 2_000);
+        assert!(change_only.height.is_some());
+    }
+
+    #[test]
+    fn unreachable_server() {
+        let _dir = data_dir();
+        let res = list_transactions(
+            &mnemonic(),
+            "127.0.0.1".to_string(),
+            1,
+            (0, 1),
+            Network::Bitcoin,
+        );
+        assert!(res.is_err());
+        assert!(res.result().is_none());
+    }
+}
@@ -1,5 +1,8 @@
 use joinstr as rust_joinstr;
-use std::str::FromStr;
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 
 use flutter_rust_bridge::frb;
 use rust_joinstr::{
@@ -8,7 +11,20 @@ use rust_joinstr::{
     nostr::{self, Fee},
     signer,
 };
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::{
+    filter::PoolFilter,
+    labels, logging, mix,
+    signing::join_pool,
+    uri,
+    verify::{Expected, VerificationReport, VerifyingSigner},
+};
 
+const POOL_PREFIX: &str = "joinstr:pool?";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Network {
     Regtest,
     Signet,
@@ -31,6 +47,12 @@ impl From<Network> for bitcoin::Network {
 pub struct Coin {
     #[frb(ignore)]
     inner: signer::Coin,
+    #[frb(ignore)]
+    mix_depth: u32,
+    #[frb(ignore)]
+    frozen: bool,
+    #[frb(ignore)]
+    label: Option<String>,
 }
 
 impl Coin {
@@ -48,11 +70,72 @@ impl Coin {
     pub fn outpoint(&self) -> String {
         self.inner.outpoint.to_string()
     }
+
+    /// Number of coinjoins this coin went through, 0 for premix coins.
+    #[frb(sync)]
+    pub fn mix_depth(&self) -> u32 {
+        self.mix_depth
+    }
+
+    #[frb(sync)]
+    pub fn is_postmix(&self) -> bool {
+        self.mix_depth > 0
+    }
+
+    /// Number of coinjoins this coin went through after the first one.
+    #[frb(sync)]
+    pub fn remix_count(&self) -> u32 {
+        self.mix_depth.saturating_sub(1)
+    }
+
+    /// Frozen coins are excluded from coinjoins.
+    #[frb(sync)]
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    #[frb(sync)]
+    pub fn label(&self) -> Option<String> {
+        self.label.clone()
+    }
+
+    pub(crate) fn outpoint_inner(&self) -> bitcoin::OutPoint {
+        self.inner.outpoint
+    }
+
+    pub(crate) fn txout(&self) -> bitcoin::TxOut {
+        self.inner.txout.clone()
+    }
+
+    pub(crate) fn script_pubkey(&self) -> bitcoin::ScriptBuf {
+        self.inner.txout.script_pubkey.clone()
+    }
+
+    pub(crate) fn coin_path(&self) -> &signer::CoinPath {
+        &self.inner.coin_path
+    }
+
+    pub(crate) fn set_mix_depth(&mut self, depth: u32) {
+        self.mix_depth = depth;
+    }
+
+    pub(crate) fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub(crate) fn set_label(&mut self, label: Option<String>) {
+        self.label = label;
+    }
 }
 
 impl From<signer::Coin> for Coin {
     fn from(value: signer::Coin) -> Self {
-        Coin { inner: value }
+        Coin {
+            inner: value,
+            mix_depth: 0,
+            frozen: false,
+            label: None,
+        }
     }
 }
 
@@ -82,24 +165,62 @@ impl From<PoolConfig> for interface::PoolConfig {
     }
 }
 
+/// Mnemonic held in memory that is zeroed on drop or on [`Mnemonic::wipe`].
+/// Background tasks (automix, coordinator) share the same secret, so wiping
+/// it also stops them from signing.
 #[frb(opaque)]
-#[derive(Clone)]
 pub struct Mnemonic {
     #[frb(ignore)]
-    inner: bip39::Mnemonic,
+    phrase: Arc<Mutex<Option<Zeroizing<String>>>>,
 }
 
 impl Mnemonic {
     #[frb(sync)]
     pub fn from_string(value: String) -> Option<Self> {
+        let value = Zeroizing::new(value);
         let inner = bip39::Mnemonic::from_str(&value).ok()?;
-        Some(Self { inner })
+        Some(Self {
+            phrase: Arc::new(Mutex::new(Some(Zeroizing::new(inner.to_string())))),
+        })
+    }
+
+    /// Zero the mnemonic, it cannot be used anymore.
+    #[frb(sync)]
+    pub fn wipe(&self) {
+        self.phrase.lock().expect("poisoned").take();
+    }
+
+    #[frb(sync)]
+    pub fn is_wiped(&self) -> bool {
+        self.phrase.lock().expect("poisoned").is_none()
+    }
+
+    /// Another handle on the same secret.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            phrase: self.phrase.clone(),
+        }
+    }
+
+    pub(crate) fn phrase(&self) -> Result<Zeroizing<String>, String> {
+        self.phrase
+            .lock()
+            .expect("poisoned")
+            .clone()
+            .ok_or("mnemonic wiped".to_string())
+    }
+
+    pub(crate) fn seed(&self) -> Result<Zeroizing<[u8; 64]>, String> {
+        let phrase = self.phrase()?;
+        let mnemonic = bip39::Mnemonic::from_str(&phrase).map_err(|e| format!("{e}"))?;
+        Ok(Zeroizing::new(mnemonic.to_seed("")))
     }
 }
 
-impl From<Mnemonic> for bip39::Mnemonic {
-    fn from(value: Mnemonic) -> Self {
-        value.inner
+/// A clone shares the secret, see [`Mnemonic::share`].
+impl Clone for Mnemonic {
+    fn clone(&self) -> Self {
+        self.share()
     }
 }
 
@@ -110,21 +231,38 @@ pub struct PeerConfig {
     pub input: Coin,
     pub output: Address,
     pub relay: String,
+    /// Sign our input in [`join_coinjoin`] even if the verification report
+    /// of the coinjoin fails, see [`CoinjoinResult::report`]. Not used by
+    /// [`initiate_coinjoin`], the initiator never verifies.
+    pub ignore_verification: bool,
 }
 
-impl From<PeerConfig> for interface::PeerConfig {
-    fn from(value: PeerConfig) -> Self {
-        interface::PeerConfig {
-            mnemonics: value.mnemonics.into(),
+impl TryFrom<PeerConfig> for interface::PeerConfig {
+    type Error = String;
+
+    fn try_from(value: PeerConfig) -> Result<Self, Self::Error> {
+        Ok(interface::PeerConfig {
+            mnemonics: bip39::Mnemonic::from_str(&value.mnemonics.phrase()?)
+                .map_err(|e| format!("{e}"))?,
             electrum_address: value.electrum_url,
             electrum_port: value.electrum_port,
             input: value.input.into(),
             output: value.output.into(),
             relay: value.relay,
-        }
+        })
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    Unknown,
+}
+
 #[frb(opaque)]
 #[derive(Clone)]
 pub struct Address {
@@ -138,6 +276,58 @@ impl Address {
         let inner = bitcoin::Address::<NetworkUnchecked>::from_str(&value).ok()?;
         Some(Self { inner })
     }
+
+    #[frb(sync)]
+    pub fn as_string(&self) -> String {
+        self.inner.assume_checked_ref().to_string()
+    }
+
+    #[frb(sync)]
+    pub fn address_type(&self) -> AddressType {
+        match self.inner.assume_checked_ref().address_type() {
+            Some(bitcoin::AddressType::P2pkh) => AddressType::P2pkh,
+            Some(bitcoin::AddressType::P2sh) => AddressType::P2sh,
+            Some(bitcoin::AddressType::P2wpkh) => AddressType::P2wpkh,
+            Some(bitcoin::AddressType::P2wsh) => AddressType::P2wsh,
+            Some(bitcoin::AddressType::P2tr) => AddressType::P2tr,
+            _ => AddressType::Unknown,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        self.inner.is_valid_for_network(network.into())
+    }
+
+    /// Network the address is encoded for, testnet & signet addresses share
+    /// the same encoding and are reported as `Testnet`.
+    #[frb(sync)]
+    pub fn network(&self) -> Network {
+        [Network::Bitcoin, Network::Regtest]
+            .into_iter()
+            .find(|n| self.is_valid_for_network(*n))
+            .unwrap_or(Network::Testnet)
+    }
+
+    #[frb(sync)]
+    pub fn script_pubkey_hex(&self) -> String {
+        self.inner
+            .assume_checked_ref()
+            .script_pubkey()
+            .to_hex_string()
+    }
+
+    pub(crate) fn as_unchecked(&self) -> &bitcoin::Address<NetworkUnchecked> {
+        &self.inner
+    }
+}
+
+impl From<bitcoin::Address> for Address {
+    fn from(value: bitcoin::Address) -> Self {
+        Address {
+            inner: value.into_unchecked(),
+        }
+    }
 }
 
 impl From<Address> for bitcoin::Address<NetworkUnchecked> {
@@ -192,6 +382,8 @@ impl ListCoinsResult {
     }
 }
 
+/// Same as [`list_mnemonic_coins`] for a mnemonic passed as a string, it
+/// is zeroed once parsed.
 #[frb(sync)]
 pub fn list_coins(
     mnemonics: String,
@@ -199,27 +391,98 @@ pub fn list_coins(
     electrum_port: u16,
     range: (u32, u32),
     network: Network,
+) -> ListCoinsResult {
+    match Mnemonic::from_string(mnemonics) {
+        Some(mnemonics) => {
+            list_mnemonic_coins(&mnemonics, electrum_url, electrum_port, range, network)
+        }
+        None => {
+            let mut res = ListCoinsResult::new();
+            res.set_error("invalid mnemonic".to_string());
+            res
+        }
+    }
+}
+
+/// List the coins of the wallet in the derivation `range`.
+#[frb(sync)]
+pub fn list_mnemonic_coins(
+    mnemonics: &Mnemonic,
+    electrum_url: String,
+    electrum_port: u16,
+    range: (u32, u32),
+    network: Network,
 ) -> ListCoinsResult {
     let mut res = ListCoinsResult::new();
+    let phrase = match mnemonics.phrase() {
+        Ok(p) => p,
+        Err(e) => {
+            res.set_error(e);
+            return res;
+        }
+    };
 
+    // the core takes the phrase as a plain String, we cannot zero its copy
     match interface::list_coins(
-        mnemonics,
+        phrase.to_string(),
         electrum_url,
         electrum_port,
         range,
         network.into(),
     ) {
-        Ok(r) => res.set(r.into_iter().map(|c| c.into()).collect()),
+        Ok(r) => {
+            let mut coins: Vec<Coin> = r.into_iter().map(|c| c.into()).collect();
+            match mix::apply_history(&mut coins) {
+                Ok(()) => {
+                    labels::apply_labels(&mut coins);
+                    res.set(coins);
+                }
+                Err(e) => res.set_error(e),
+            }
+        }
         Err(e) => res.set_error(format!("{e}")),
     }
 
     res
 }
 
+#[frb(opaque)]
+pub struct EmptyResult {
+    error: Option<String>,
+}
+
+impl EmptyResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self { error: None }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        self.error.is_some()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
 #[frb(opaque)]
 pub struct CoinjoinResult {
     txid: Option<String>,
     error: Option<String>,
+    report: Option<VerificationReport>,
 }
 
 impl CoinjoinResult {
@@ -238,9 +501,18 @@ impl CoinjoinResult {
         Self {
             txid: None,
             error: None,
+            report: None,
         }
     }
 
+    /// Verification report of the coinjoin we were asked to sign, None if
+    /// the coinjoin failed before or was signed by the initiator, see
+    /// [`initiate_coinjoin`].
+    #[frb(sync)]
+    pub fn report(&self) -> Option<VerificationReport> {
+        self.report.clone()
+    }
+
     #[frb(sync)]
     pub fn result(&self) -> Option<String> {
         self.txid.clone()
@@ -260,13 +532,88 @@ impl CoinjoinResult {
     pub fn set_error(&mut self, error: String) {
         self.error = Some(error);
     }
+
+    pub(crate) fn set_report(&mut self, report: Option<VerificationReport>) {
+        self.report = report;
+    }
+}
+
+#[frb(opaque)]
+pub struct StringResult {
+    value: Option<String>,
+    error: Option<String>,
+}
+
+impl StringResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            value: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.value.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.value.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<String> {
+        self.value.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: String) {
+        self.value = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
 }
 
+/// Create a pool from `config` and join it with `peer`.
+///
+/// The core signs the initiator input itself, the coinjoin transaction is
+/// not verified whatever `peer.ignore_verification` says and the result
+/// carries no report. Use [`join_coinjoin`] to sign a verified coinjoin.
 #[frb(sync)]
 pub fn initiate_coinjoin(config: PoolConfig, peer: PeerConfig) -> CoinjoinResult {
     let mut res = CoinjoinResult::new();
-    match interface::initiate_coinjoin(config.into(), peer.into()) {
-        Ok(txid) => res.set(txid.to_string()),
+    if labels::is_frozen(&peer.input.outpoint()) {
+        res.set_error("input coin is frozen".to_string());
+        return res;
+    }
+    if let Err(e) = mix::check_history() {
+        res.set_error(e);
+        return res;
+    }
+    let depth = peer.input.mix_depth();
+    let peer = match interface::PeerConfig::try_from(peer) {
+        Ok(p) => p,
+        Err(e) => {
+            res.set_error(e);
+            return res;
+        }
+    };
+    log::warn!("the initiator signs the coinjoin without verifying it");
+    match interface::initiate_coinjoin(config.into(), peer) {
+        Ok(txid) => match mix::record_coinjoin(&txid.to_string(), depth) {
+            Ok(()) => res.set(txid.to_string()),
+            Err(e) => res.set_error(e),
+        },
         Err(e) => res.set_error(format!("{e}")),
     }
 
@@ -311,16 +658,141 @@ impl Pool {
             .flatten()
     }
 
+    /// Fee of the pool, None if the pool does not use a fixed fee.
     #[frb(sync)]
     pub fn fee(&self) -> Option<u32> {
-        self.inner.payload.as_ref().map(|p| {
+        self.inner.payload.as_ref().and_then(|p| {
             if let Fee::Fixed(fee) = p.fee {
-                fee
+                Some(fee)
             } else {
-                unreachable!()
+                None
             }
         })
     }
+
+    /// Unix timestamp after which the pool does not accept peers anymore.
+    #[frb(sync)]
+    pub fn timeout(&self) -> Option<u64> {
+        self.inner.payload.as_ref().map(|p| p.timeout)
+    }
+
+    pub(crate) fn network_inner(&self) -> bitcoin::Network {
+        self.inner.network
+    }
+
+    #[frb(sync)]
+    pub fn id(&self) -> String {
+        self.inner.id.clone()
+    }
+
+    /// Serialize a reference to this pool (its id & relays) as an URI,
+    /// suitable for a link or a QR code.
+    #[frb(sync)]
+    pub fn to_uri(&self) -> String {
+        let mut pairs = vec![("id", self.inner.id.as_str())];
+        if let Some(payload) = &self.inner.payload {
+            pairs.extend(payload.relays.iter().map(|r| ("relay", r.as_str())));
+        }
+        uri::build(POOL_PREFIX, &pairs)
+    }
+
+    /// Fetch the pool referenced by `uri` from its relays.
+    #[frb(sync)]
+    pub fn from_uri(uri: String, back: u64, timeout: u64) -> PoolResult {
+        let mut res = PoolResult::new();
+        let Some(pairs) = uri::parse(&uri, POOL_PREFIX) else {
+            res.set_error("invalid pool uri".to_string());
+            return res;
+        };
+        let id = pairs
+            .iter()
+            .find(|(k, _)| k == "id")
+            .map(|(_, v)| v.clone());
+        let relays: Vec<_> = pairs
+            .into_iter()
+            .filter(|(k, _)| k == "relay")
+            .map(|(_, v)| v)
+            .collect();
+        let Some(id) = id else {
+            res.set_error("pool uri without id".to_string());
+            return res;
+        };
+        if relays.is_empty() {
+            res.set_error("pool uri without relay".to_string());
+            return res;
+        }
+
+        let mut errors = Vec::new();
+        for relay in relays {
+            match interface::list_pools(back, timeout, relay.clone()) {
+                Ok(pools) => {
+                    if let Some(pool) = pools.into_iter().find(|p| p.id == id) {
+                        let pool: Pool = pool.into();
+                        if pool.denomination_sat().is_none() || pool.peers().unwrap_or(0) < 2 {
+                            res.set_error("invalid pool payload".to_string());
+                        } else {
+                            res.set(pool);
+                        }
+                        return res;
+                    }
+                }
+                Err(e) => errors.push(format!("{relay}: {e}")),
+            }
+        }
+        if errors.is_empty() {
+            res.set_error("pool not found".to_string());
+        } else {
+            res.set_error(errors.join(", "));
+        }
+
+        res
+    }
+}
+
+#[frb(opaque)]
+pub struct PoolResult {
+    pool: Option<Pool>,
+    error: Option<String>,
+}
+
+impl PoolResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            pool: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.pool.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.pool.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Pool> {
+        self.pool.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Pool) {
+        self.pool = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
 }
 
 impl From<nostr::Pool> for Pool {
@@ -396,12 +868,63 @@ pub fn list_pools(back: u64, timeout: u64, relay: String) -> ListPoolsResult {
     res
 }
 
+/// Same as [`list_pools`], only the pools matching `filter` are returned,
+/// in the order it asks for.
+#[frb(sync)]
+pub fn list_pools_filtered(
+    back: u64,
+    timeout: u64,
+    relay: String,
+    filter: PoolFilter,
+) -> ListPoolsResult {
+    let mut res = list_pools(back, timeout, relay);
+    if let Some(pools) = res.result() {
+        res.set(filter.apply(pools));
+    }
+
+    res
+}
+
+/// Join `pool`, the coinjoin transaction is verified before our input is
+/// signed and the coinjoin fails if any check does not pass.
 #[frb(sync)]
 pub fn join_coinjoin(pool: Pool, peer: PeerConfig) -> CoinjoinResult {
     let mut res = CoinjoinResult::new();
-    match interface::join_coinjoin(pool.into(), peer.into()) {
-        Ok(txid) => res.set(txid.to_string()),
-        Err(e) => res.set_error(format!("{e}")),
+    if labels::is_frozen(&peer.input.outpoint()) {
+        res.set_error("input coin is frozen".to_string());
+        return res;
+    }
+    let network = pool.network_inner();
+    let signer = peer
+        .mnemonics
+        .phrase()
+        .and_then(|phrase| {
+            signer::WpkhHotSigner::new_from_mnemonics(network, &phrase).map_err(|e| format!("{e}"))
+        })
+        .and_then(|inner| {
+            Ok(VerifyingSigner {
+                inner,
+                expected: Expected::new(&pool, &peer.input, &peer.output)?,
+                force: peer.ignore_verification,
+                report: Mutex::new(None),
+            })
+        });
+    let result = signer.and_then(|signer| {
+        let result = join_pool(
+            pool,
+            &peer.relay,
+            (&peer.electrum_url, peer.electrum_port),
+            peer.input,
+            peer.output,
+            network,
+            &signer,
+        );
+        res.set_report(signer.report.into_inner().expect("poisoned"));
+        result
+    });
+    match result {
+        Ok(txid) => res.set(txid),
+        Err(e) => res.set_error(e),
     }
 
     res
@@ -409,5 +932,128 @@ pub fn join_coinjoin(pool: Pool, peer: PeerConfig) -> CoinjoinResult {
 
 #[frb(init)]
 pub fn init_app() {
+    // before the default utils, the forwarder writes to the console logger
+    // they would install on mobile
+    logging::init();
     flutter_rust_bridge::setup_default_user_utils();
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{coin, data_dir, mnemonic, pool, script, transaction, MockElectrum};
+
+    fn peer(input: Coin, mnemonics: Mnemonic) -> PeerConfig {
+        PeerConfig {
+            mnemonics,
+            electrum_url: "127.0.0.1".to_string(),
+            electrum_port: 1,
+            input,
+            output: bitcoin::Address::from_script(&script(2), bitcoin::Network::Regtest)
+                .unwrap()
+                .into(),
+            relay: "wss://relay.example".to_string(),
+            ignore_verification: false,
+        }
+    }
+
+    fn pool_config() -> PoolConfig {
+        PoolConfig {
+            denomination: 0.001,
+            fee: 1,
+            max_duration: 60,
+            peers: 2,
+            network: Network::Regtest,
+        }
+    }
+
+    #[test]
+    fn clone_shares_the_secret() {
+        let mnemonics = mnemonic();
+        let clone = mnemonics.clone();
+        assert_eq!(*clone.phrase().unwrap(), *mnemonics.phrase().unwrap());
+        clone.wipe();
+        assert!(mnemonics.is_wiped());
+        assert!(mnemonics.phrase().is_err());
+    }
+
+    #[test]
+    fn list_coins_invalid_mnemonic() {
+        let res = list_coins(
+            "not a mnemonic".to_string(),
+            String::new(),
+            0,
+            (0, 20),
+            Network::Regtest,
+        );
+        assert_eq!(res.error().as_deref(), Some("invalid mnemonic"));
+
+        let wiped = mnemonic();
+        wiped.wipe();
+        let res = list_mnemonic_coins(&wiped, String::new(), 0, (0, 20), Network::Regtest);
+        assert_eq!(res.error().as_deref(), Some("mnemonic wiped"));
+    }
+
+    #[test]
+    fn list_coins_from_server() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let signer = signer::WpkhHotSigner::new_from_mnemonics(
+            bitcoin::Network::Regtest,
+            &mnemonic().phrase().unwrap(),
+        )
+        .unwrap();
+        let funding = transaction(
+            &[OutPoint::new(Txid::all_zeros(), 0)],
+            &[(signer.recv_addr_at(1).script_pubkey(), 50_000)],
+        );
+        let txid = electrum.confirm(funding);
+        let outpoint = OutPoint::new(txid, 0).to_string();
+        assert!(labels::freeze_coin(outpoint.clone(), true).is_ok());
+
+        let res = list_mnemonic_coins(
+            &mnemonic(),
+            electrum.url(),
+            electrum.port(),
+            (0, 20),
+            Network::Regtest,
+        );
+        let coins = res.result().unwrap();
+        assert_eq!(coins.len(), 1);
+        assert_eq!(coins[0].outpoint(), outpoint);
+        assert_eq!(coins[0].amount_sat(), 50_000);
+        assert!(coins[0].is_frozen());
+    }
+
+    #[test]
+    fn join_refused_before_the_pool() {
+        let _dir = data_dir();
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+        let input = coin(outpoint, script(1), 100_500);
+        let announced = pool("pool", 100_000, 2, 1, 0);
+
+        // nothing reaches the relay for a frozen input
+        assert!(labels::freeze_coin(outpoint.to_string(), true).is_ok());
+        let res = join_coinjoin(announced.clone(), peer(input.clone(), mnemonic()));
+        assert_eq!(res.error().as_deref(), Some("input coin is frozen"));
+        let res = initiate_coinjoin(pool_config(), peer(input.clone(), mnemonic()));
+        assert_eq!(res.error().as_deref(), Some("input coin is frozen"));
+        assert!(labels::freeze_coin(outpoint.to_string(), false).is_ok());
+
+        let wiped = mnemonic();
+        wiped.wipe();
+        let res = join_coinjoin(announced, peer(input.clone(), wiped.clone()));
+        assert_eq!(res.error().as_deref(), Some("mnemonic wiped"));
+        assert!(res.report().is_none());
+        let res = initiate_coinjoin(pool_config(), peer(input.clone(), wiped));
+        assert_eq!(res.error().as_deref(), Some("mnemonic wiped"));
+
+        // the expected coinjoin cannot be checked without a denomination
+        let mut inner: nostr::Pool = pool("empty", 100_000, 2, 1, 0).into();
+        inner.payload = None;
+        let res = join_coinjoin(inner.into(), peer(input, mnemonic()));
+        assert_eq!(res.error().as_deref(), Some("pool without denomination"));
+    }
+}
@@ -0,0 +1,261 @@
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{
+    joinstr::{Coin, EmptyResult, StringResult},
+    store,
+};
+
+/// Label types defined in BIP329.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+/// A BIP329 label record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Label {
+    #[serde(rename = "type")]
+    pub kind: LabelType,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+    /// Other fields of the record (e.g. `height`, `fee`, `keypath`), kept
+    /// as is so they survive an import/export round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Label {
+    fn new(kind: LabelType, reference: String) -> Self {
+        Label {
+            kind,
+            reference,
+            label: None,
+            origin: None,
+            spendable: None,
+            extra: Map::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.label.is_none()
+            && self.origin.is_none()
+            && self.spendable.is_none()
+            && self.extra.is_empty()
+    }
+}
+
+/// Edit the record of type `kind` for `reference`, records left empty are
+/// dropped.
+fn edit(kind: LabelType, reference: String, f: impl FnOnce(&mut Label)) -> Result<(), String> {
+    store::update(|s| {
+        let pos = match s
+            .labels
+            .iter()
+            .position(|l| l.kind == kind && l.reference == reference)
+        {
+            Some(pos) => pos,
+            None => {
+                s.labels.push(Label::new(kind, reference));
+                s.labels.len() - 1
+            }
+        };
+        f(&mut s.labels[pos]);
+        if s.labels[pos].is_empty() {
+            s.labels.remove(pos);
+        }
+    })
+}
+
+/// Whether the coin at `outpoint` is frozen, frozen coins must never be
+/// selected as coinjoin inputs.
+pub(crate) fn is_frozen(outpoint: &str) -> bool {
+    store::load()
+        .map(|s| {
+            s.labels.iter().any(|l| {
+                l.kind == LabelType::Output && l.reference == outpoint && l.spendable == Some(false)
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Set the freeze flag and label of coins from the local state.
+pub(crate) fn apply_labels(coins: &mut [Coin]) {
+    let labels = store::load().map(|s| s.labels).unwrap_or_default();
+    for coin in coins {
+        let outpoint = coin.outpoint();
+        if let Some(l) = labels
+            .iter()
+            .find(|l| l.kind == LabelType::Output && l.reference == outpoint)
+        {
+            coin.set_frozen(l.spendable == Some(false));
+            coin.set_label(l.label.clone());
+        }
+    }
+}
+
+/// Freeze or unfreeze the coin at `outpoint`.
+#[frb(sync)]
+pub fn freeze_coin(outpoint: String, frozen: bool) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    if let Err(e) = edit(LabelType::Output, outpoint, |l| {
+        l.spendable = if frozen { Some(false) } else { None };
+    }) {
+        res.set_error(e);
+    }
+
+    res
+}
+
+/// Set (or remove if `label` is None) the label of an item.
+#[frb(sync)]
+pub fn set_label(label_type: LabelType, reference: String, label: Option<String>) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    if let Err(e) = edit(label_type, reference, |l| l.label = label) {
+        res.set_error(e);
+    }
+
+    res
+}
+
+/// Export all labels in the BIP329 JSON Lines format.
+#[frb(sync)]
+pub fn export_labels() -> StringResult {
+    let mut res = StringResult::new();
+    match store::load() {
+        Ok(state) => {
+            let lines: Result<Vec<_>, _> = state.labels.iter().map(serde_json::to_string).collect();
+            match lines {
+                Ok(lines) => res.set(lines.join("\n")),
+                Err(e) => res.set_error(format!("{e}")),
+            }
+        }
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+/// Import labels in the BIP329 JSON Lines format, imported records replace
+/// the existing ones with the same type & reference. Nothing is imported if
+/// any line is invalid.
+#[frb(sync)]
+pub fn import_labels(jsonl: String) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    let mut labels = Vec::new();
+    for (i, line) in jsonl.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Label>(line) {
+            Ok(l) => labels.push(l),
+            Err(e) => {
+                res.set_error(format!("line {}: {e}", i + 1));
+                return res;
+            }
+        }
+    }
+
+    if let Err(e) = store::update(|s| {
+        for label in labels {
+            s.labels
+                .retain(|l| !(l.kind == label.kind && l.reference == label.reference));
+            s.labels.push(label);
+        }
+    }) {
+        res.set_error(e);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::data_dir;
+
+    const OUTPOINT: &str = "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0";
+
+    #[test]
+    fn export_import_round_trip() {
+        let dir = data_dir();
+        assert!(set_label(LabelType::Tx, "txid".to_string(), Some("rent".to_string())).is_ok());
+        assert!(freeze_coin(OUTPOINT.to_string(), true).is_ok());
+        assert!(is_frozen(OUTPOINT));
+
+        let exported = export_labels().result().unwrap();
+        let expected = format!(
+            r#"{{"type":"tx","ref":"txid","label":"rent"}}
+{{"type":"output","ref":"{OUTPOINT}","spendable":false}}"#
+        );
+        assert_eq!(exported, expected);
+
+        // importing into a fresh state restores the same records
+        drop(dir);
+        let _dir = data_dir();
+        assert!(!is_frozen(OUTPOINT));
+        assert!(import_labels(exported.clone()).is_ok());
+        assert!(is_frozen(OUTPOINT));
+        assert_eq!(export_labels().result().unwrap(), exported);
+    }
+
+    #[test]
+    fn import_replaces_and_validates() {
+        let _dir = data_dir();
+        assert!(set_label(LabelType::Addr, "addr".to_string(), Some("old".to_string())).is_ok());
+        let res = import_labels(
+            r#"{"type":"addr","ref":"addr","label":"new"}
+
+{"type":"pubkey","ref":"key","label":"cold"}"#
+                .to_string(),
+        );
+        assert!(res.is_ok());
+        let exported = export_labels().result().unwrap();
+        assert_eq!(exported.lines().count(), 2);
+        assert!(exported.contains(r#""label":"new""#));
+
+        // an invalid line rejects the whole import
+        let res = import_labels(
+            r#"{"type":"tx","ref":"a"}
+not json"#
+                .to_string(),
+        );
+        assert_eq!(
+            res.error().as_deref().map(|e| e.starts_with("line 2")),
+            Some(true)
+        );
+        assert_eq!(export_labels().result().unwrap(), exported);
+    }
+
+    #[test]
+    fn other_fields_kept() {
+        let _dir = data_dir();
+        let record = format!(
+            r#"{{"type":"output","ref":"{OUTPOINT}","label":"salary","fmv":{{"USD":43000}},"height":800000,"keypath":"/0/3"}}"#
+        );
+        assert!(import_labels(record.clone()).is_ok());
+        assert_eq!(export_labels().result().unwrap(), record);
+
+        // editing the record keeps them
+        assert!(freeze_coin(OUTPOINT.to_string(), true).is_ok());
+        assert!(set_label(LabelType::Output, OUTPOINT.to_string(), None).is_ok());
+        let exported = export_labels().result().unwrap();
+        assert!(exported.contains(r#""spendable":false"#));
+        assert!(exported.contains(r#""height":800000"#));
+        assert!(exported.contains(r#""fmv":{"USD":43000}"#));
+        assert!(!exported.contains("salary"));
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flutter_rust_bridge::frb;
+
+use crate::frb_generated::StreamSink;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(value: log::Level) -> Self {
+        match value {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Unix timestamp in milliseconds
+    pub time: u64,
+    pub level: LogLevel,
+    /// Module emitting the record, e.g. `joinstr::joinstr`
+    pub target: String,
+    pub message: String,
+}
+
+struct Config {
+    sink: Option<StreamSink<LogEntry>>,
+    level: LogLevel,
+    targets: Vec<String>,
+}
+
+static CONFIG: Mutex<Config> = Mutex::new(Config {
+    sink: None,
+    level: LogLevel::Info,
+    targets: Vec::new(),
+});
+
+fn enabled(config: &Config, level: LogLevel, target: &str) -> bool {
+    level <= config.level
+        && (config.targets.is_empty()
+            || config
+                .targets
+                .iter()
+                .any(|t| target == t || target.starts_with(&format!("{t}::"))))
+}
+
+/// The platform console, it gets all records whatever the level and targets
+/// forwarded to the app.
+static CONSOLE: OnceLock<Option<Box<dyn log::Log>>> = OnceLock::new();
+
+/// The console logger `setup_default_user_utils` would install, only one
+/// logger can be set so the forwarder writes to it.
+#[allow(unreachable_code)]
+fn platform_console() -> Option<Box<dyn log::Log>> {
+    #[cfg(target_os = "android")]
+    return Some(Box::new(android_logger::AndroidLogger::new(
+        android_logger::Config::default().with_max_level(log::LevelFilter::Trace),
+    )));
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    return Some(Box::new(
+        oslog::OsLogger::new("frb_user").level_filter(log::LevelFilter::Trace),
+    ));
+    None
+}
+
+fn console() -> Option<&'static dyn log::Log> {
+    CONSOLE.get().and_then(|c| c.as_deref())
+}
+
+/// Forward the records of this crate and of the joinstr core to the app and
+/// to the platform console.
+struct Forwarder;
+
+impl log::Log for Forwarder {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        if console().map(|c| c.enabled(metadata)).unwrap_or(false) {
+            return true;
+        }
+        let config = CONFIG.lock().expect("poisoned");
+        config.sink.is_some() && enabled(&config, metadata.level().into(), metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(console) = console() {
+            console.log(record);
+        }
+        let mut config = CONFIG.lock().expect("poisoned");
+        if !enabled(&config, record.level().into(), record.target()) {
+            return;
+        }
+        let Some(sink) = &config.sink else {
+            return;
+        };
+        let entry = LogEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            level: record.level().into(),
+            target: record.target().to_string(),
+            message: format!("{}", record.args()),
+        };
+        // the app stopped listening
+        if sink.add(entry).is_err() {
+            config.sink = None;
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(console) = console() {
+            console.flush();
+        }
+    }
+}
+
+static FORWARDER: Forwarder = Forwarder;
+
+/// Records below this level are dropped before reaching the forwarder, the
+/// console gets them all.
+fn max_level(level: LogLevel) -> log::LevelFilter {
+    match console() {
+        Some(_) => log::LevelFilter::Trace,
+        None => level.into(),
+    }
+}
+
+/// Install the log forwarder, must run before any other logger is set.
+pub(crate) fn init() {
+    CONSOLE.get_or_init(platform_console);
+    if log::set_logger(&FORWARDER).is_ok() {
+        log::set_max_level(max_level(LogLevel::Info));
+    }
+}
+
+/// Receive the log records matching the current level & targets, a new
+/// stream replaces the previous one.
+pub fn log_stream(sink: StreamSink<LogEntry>) -> Result<(), String> {
+    CONFIG.lock().expect("poisoned").sink = Some(sink);
+    Ok(())
+}
+
+/// Only forward records at `level` or more severe.
+#[frb(sync)]
+pub fn set_log_level(level: LogLevel) {
+    CONFIG.lock().expect("poisoned").level = level;
+    log::set_max_level(max_level(level));
+}
+
+/// Only forward records from `targets` and their submodules, e.g.
+/// `joinstr` also matches `joinstr::nostr`. An empty list forwards all
+/// records.
+#[frb(sync)]
+pub fn set_log_targets(targets: Vec<String>) {
+    CONFIG.lock().expect("poisoned").targets = targets;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Console;
+
+    impl log::Log for Console {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            RECORDS.lock().unwrap().push(format!("{}", record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn console_gets_all_records() {
+        assert!(CONSOLE.set(Some(Box::new(Console))).is_ok());
+        set_log_level(LogLevel::Warn);
+        set_log_targets(vec!["joinstr".to_string()]);
+
+        let metadata = log::Metadata::builder()
+            .level(log::Level::Debug)
+            .target("dart_joinstr::api")
+            .build();
+        assert!(log::Log::enabled(&Forwarder, &metadata));
+        assert_eq!(max_level(LogLevel::Warn), log::LevelFilter::Trace);
+        // no stream is attached, the console still gets the record
+        log::Log::log(
+            &Forwarder,
+            &log::Record::builder()
+                .args(format_args!("registered"))
+                .level(log::Level::Debug)
+                .target("dart_joinstr::api")
+                .build(),
+        );
+        assert_eq!(*RECORDS.lock().unwrap(), vec!["registered".to_string()]);
+
+        set_log_targets(Vec::new());
+        set_log_level(LogLevel::Info);
+    }
+}
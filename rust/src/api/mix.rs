@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::{OutPoint, Transaction};
+
+use super::{
+    electrum,
+    joinstr::{Coin, ListCoinsResult},
+    store,
+};
+
+/// Check the local coinjoin history can be read before taking part in a
+/// coinjoin, its outputs would be reported unmixed if it cannot be recorded.
+pub(crate) fn check_history() -> Result<(), String> {
+    store::load().map(|_| ())
+}
+
+/// Record a coinjoin we took part in, its outputs are one level deeper
+/// than the input we spent.
+pub(crate) fn record_coinjoin(txid: &str, input_depth: u32) -> Result<(), String> {
+    store::update(|s| {
+        s.coinjoins.insert(txid.to_string(), input_depth + 1);
+    })
+    .map_err(|e| format!("coinjoin {txid} not recorded: {e}"))
+}
+
+/// Set the mix depth of coins from the local coinjoin history, fails if the
+/// history cannot be read rather than reporting every coin unmixed.
+pub(crate) fn apply_history(coins: &mut [Coin]) -> Result<(), String> {
+    let history = store::load()?.coinjoins;
+    for coin in coins {
+        let txid = coin.outpoint_inner().txid.to_string();
+        if let Some(depth) = history.get(&txid) {
+            coin.set_mix_depth(*depth);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `tx` looks like an equal-output coinjoin that produced `outpoint`.
+pub(crate) fn is_coinjoin_output(tx: &Transaction, outpoint: &OutPoint) -> bool {
+    let Some(ours) = tx.output.get(outpoint.vout as usize) else {
+        return false;
+    };
+    let equal = tx.output.iter().filter(|o| o.value == ours.value).count();
+    tx.input.len() > 1 && equal > 1
+}
+
+/// Complete the mix depth of coins that are not in the local history by
+/// looking at their funding transaction: a coin created by an equal-output
+/// transaction is considered mixed at least once.
+#[frb(sync)]
+pub fn analyze_coins(
+    coins: Vec<Coin>,
+    electrum_url: String,
+    electrum_port: u16,
+) -> ListCoinsResult {
+    let mut res = ListCoinsResult::new();
+    let mut coins = coins;
+    if let Err(e) = apply_history(&mut coins) {
+        res.set_error(e);
+        return res;
+    }
+
+    let mut client = match electrum::Client::new(&electrum_url, electrum_port) {
+        Ok(c) => c,
+        Err(e) => {
+            res.set_error(e);
+            return res;
+        }
+    };
+
+    let mut txs = BTreeMap::new();
+    for coin in coins.iter_mut().filter(|c| c.mix_depth() == 0) {
+        let outpoint = coin.outpoint_inner();
+        if !txs.contains_key(&outpoint.txid) {
+            match client.get_transaction(&outpoint.txid) {
+                Ok(tx) => {
+                    txs.insert(outpoint.txid, tx);
+                }
+                Err(e) => {
+                    res.set_error(e);
+                    return res;
+                }
+            }
+        }
+        if is_coinjoin_output(&txs[&outpoint.txid], &outpoint) {
+            coin.set_mix_depth(1);
+        }
+    }
+    res.set(coins);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{coin, data_dir, script, temp_path, transaction, MockElectrum};
+
+    #[test]
+    fn equal_outputs_are_mixed() {
+        let _guard = data_dir();
+        let server = MockElectrum::start();
+        let funding = server.confirm(transaction(
+            &[OutPoint::null()],
+            &[(script(1), 60_000), (script(2), 60_000)],
+        ));
+        let coinjoin = server.confirm(transaction(
+            &[OutPoint::new(funding, 0), OutPoint::new(funding, 1)],
+            &[(script(3), 50_000), (script(4), 50_000)],
+        ));
+        let change = server.confirm(transaction(
+            &[OutPoint::new(funding, 0), OutPoint::new(funding, 1)],
+            &[(script(5), 50_000), (script(6), 60_000)],
+        ));
+
+        let coins = vec![
+            coin(OutPoint::new(coinjoin, 0), script(3), 50_000),
+            coin(OutPoint::new(change, 0), script(5), 50_000),
+        ];
+        let res = analyze_coins(coins, server.url(), server.port());
+        let coins = res.result().unwrap();
+        assert_eq!(coins[0].mix_depth(), 1);
+        assert_eq!(coins[1].mix_depth(), 0);
+    }
+
+    #[test]
+    fn recorded_coinjoin_depth() {
+        let _guard = data_dir();
+        let txid = Txid::from_byte_array([7; 32]);
+        record_coinjoin(&txid.to_string(), 2).unwrap();
+        let mut coins = vec![
+            coin(OutPoint::new(txid, 0), script(1), 50_000),
+            coin(OutPoint::new(Txid::all_zeros(), 0), script(2), 50_000),
+        ];
+        apply_history(&mut coins).unwrap();
+        assert_eq!(coins[0].mix_depth(), 3);
+        assert_eq!(coins[1].mix_depth(), 0);
+    }
+
+    #[test]
+    fn unreadable_history() {
+        let _guard = data_dir();
+        let dir = temp_path("unreadable");
+        assert!(store::set_data_dir(dir.display().to_string()).is_ok());
+        // the state file can no longer be read
+        std::fs::create_dir(dir.join("dart_joinstr.json")).unwrap();
+
+        assert!(check_history().is_err());
+        assert!(record_coinjoin(&Txid::all_zeros().to_string(), 0).is_err());
+        let coins = vec![coin(OutPoint::null(), script(1), 50_000)];
+        let res = analyze_coins(coins, "127.0.0.1".to_string(), 1);
+        assert!(res.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1 +1,22 @@
+pub mod automix;
+pub mod bip21;
+pub mod coordinator;
+pub mod derivation;
+pub(crate) mod electrum;
+pub mod fees;
+pub mod filter;
+pub mod history;
 pub mod joinstr;
+pub mod labels;
+pub mod logging;
+pub mod mix;
+pub mod probe;
+mod relay;
+pub mod send;
+pub mod signing;
+pub mod store;
+pub mod summary;
+mod uri;
+pub mod vault;
+pub mod verify;
+pub mod watch;
@@ -0,0 +1,245 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::{self, constants::genesis_block};
+use serde_json::{json, Value};
+
+use super::{
+    electrum,
+    joinstr::Network,
+    relay::{self, Relay},
+};
+
+/// Kind of the event published to test write permission, ephemeral events
+/// (NIP-16) are not stored by the relay.
+const PROBE_KIND: u64 = 21_059;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Health of a Nostr relay.
+#[derive(Debug, Clone, Default)]
+pub struct RelayProbe {
+    pub url: String,
+    pub connected: bool,
+    /// Round-trip of a subscription request, in milliseconds.
+    pub latency_ms: Option<u64>,
+    /// NIPs advertised in the relay information document (NIP-11).
+    pub supported_nips: Vec<u32>,
+    pub name: Option<String>,
+    pub software: Option<String>,
+    /// Whether the relay accepted an event from an unknown key.
+    pub writable: bool,
+    /// Why the relay refused our event, e.g. `auth-required: ...`.
+    pub write_error: Option<String>,
+    /// Whether we authenticated (NIP-42) with the throwaway probe key, only
+    /// tried if the relay asked for it.
+    pub authenticated: bool,
+    pub error: Option<String>,
+}
+
+/// Fetch the NIP-11 relay information document.
+fn relay_info(url: &str) -> Result<Value, String> {
+    let http = url
+        .replacen("wss://", "https://", 1)
+        .replacen("ws://", "http://", 1);
+    let info = ureq::get(&http)
+        .set("Accept", "application/nostr+json")
+        .timeout(TIMEOUT)
+        .call()
+        .map_err(|e| format!("{e}"))?
+        .into_string()
+        .map_err(|e| format!("{e}"))?;
+    serde_json::from_str(&info).map_err(|e| format!("{e}"))
+}
+
+fn probe(url: &str, probe: &mut RelayProbe) -> Result<(), String> {
+    let key = relay::ephemeral_key();
+    let mut relay = Relay::connect(url, key)?;
+    probe.connected = true;
+
+    let start = Instant::now();
+    relay.send(json!(["REQ", "probe", {"limit": 0}]))?;
+    loop {
+        let message = relay.recv()?;
+        let kind = message.get(0).and_then(Value::as_str);
+        if matches!(kind, Some("EOSE") | Some("CLOSED")) {
+            break;
+        }
+    }
+    probe.latency_ms = Some(start.elapsed().as_millis() as u64);
+    relay.send(json!(["CLOSE", "probe"]))?;
+
+    let event = relay::sign_event(&key, PROBE_KIND, json!([]), "probe");
+    let (accepted, reason) = relay.publish(&event)?;
+    probe.writable = accepted;
+    probe.authenticated = relay.authenticated();
+    if !accepted {
+        probe.write_error = Some(reason);
+    }
+    relay.close();
+    Ok(())
+}
+
+/// Check that the relay at `url` is reachable, how fast it answers and
+/// whether it accepts our events.
+#[frb(sync)]
+pub fn probe_relay(url: String) -> RelayProbe {
+    let mut res = RelayProbe {
+        url: url.clone(),
+        ..Default::default()
+    };
+    // the information document is optional, a relay may work without it
+    if let Ok(info) = relay_info(&url) {
+        res.supported_nips = info
+            .get("supported_nips")
+            .and_then(Value::as_array)
+            .map(|nips| {
+                nips.iter()
+                    .filter_map(|n| n.as_u64().map(|n| n as u32))
+                    .collect()
+            })
+            .unwrap_or_default();
+        res.name = info.get("name").and_then(Value::as_str).map(String::from);
+        res.software = info
+            .get("software")
+            .and_then(Value::as_str)
+            .map(String::from);
+    }
+    if let Err(e) = probe(&url, &mut res) {
+        res.error = Some(e);
+    }
+
+    res
+}
+
+/// Probe all `urls` in parallel, best relays first: reachable, writable,
+/// then by latency.
+#[frb(sync)]
+pub fn probe_relays(urls: Vec<String>) -> Vec<RelayProbe> {
+    let mut probes: Vec<RelayProbe> = thread::scope(|s| {
+        let handles: Vec<_> = urls
+            .into_iter()
+            .map(|url| (url.clone(), s.spawn(move || probe_relay(url))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(url, h)| {
+                h.join().unwrap_or_else(|_| RelayProbe {
+                    url,
+                    error: Some("probe panicked".to_string()),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    });
+    probes.sort_by_key(|p| (!p.connected, !p.writable, p.latency_ms.unwrap_or(u64::MAX)));
+    probes
+}
+
+pub struct ElectrumConfig {
+    pub url: String,
+    pub port: u16,
+    pub network: Network,
+}
+
+/// Health of an Electrum server.
+#[derive(Debug, Clone, Default)]
+pub struct ElectrumProbe {
+    pub connected: bool,
+    pub server_version: Option<String>,
+    pub protocol_version: Option<String>,
+    pub tip_height: Option<u32>,
+    /// Whether the server follows the chain of the configured network.
+    pub network_match: bool,
+    /// Round-trip of a ping, in milliseconds.
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+fn probe_server(config: &ElectrumConfig, probe: &mut ElectrumProbe) -> Result<(), String> {
+    let mut client = electrum::Client::new(&config.url, config.port)?;
+    probe.connected = true;
+
+    let (server, protocol) = client.server_version()?;
+    probe.server_version = Some(server);
+    probe.protocol_version = Some(protocol);
+
+    let start = Instant::now();
+    client.ping()?;
+    probe.latency_ms = Some(start.elapsed().as_millis() as u64);
+
+    probe.tip_height = Some(client.tip_height()?);
+
+    let network: bitcoin::Network = config.network.into();
+    let genesis = client.block_header(0)?.block_hash();
+    probe.network_match = genesis == genesis_block(network).block_hash();
+    if !probe.network_match {
+        return Err(format!("the server does not follow the {network} chain"));
+    }
+    Ok(())
+}
+
+/// Check that the Electrum server is reachable and serves the chain of the
+/// configured network, to run before scanning the wallet.
+#[frb(sync)]
+pub fn probe_electrum(config: ElectrumConfig) -> ElectrumProbe {
+    let mut res = ElectrumProbe::default();
+    if let Err(e) = probe_server(&config, &mut res) {
+        res.error = Some(e);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockElectrum;
+
+    #[test]
+    fn electrum_on_configured_network() {
+        let server = MockElectrum::start();
+        server.mine(5);
+        let probe = probe_electrum(ElectrumConfig {
+            url: server.url(),
+            port: server.port(),
+            network: Network::Regtest,
+        });
+        assert!(probe.connected);
+        assert!(probe.network_match);
+        assert_eq!(probe.tip_height, Some(5));
+        assert_eq!(probe.protocol_version.as_deref(), Some("1.4"));
+        assert!(probe.latency_ms.is_some());
+        assert_eq!(probe.error, None);
+    }
+
+    #[test]
+    fn electrum_on_other_network() {
+        let server = MockElectrum::start();
+        let probe = probe_electrum(ElectrumConfig {
+            url: server.url(),
+            port: server.port(),
+            network: Network::Bitcoin,
+        });
+        assert!(probe.connected);
+        assert!(!probe.network_match);
+        assert!(probe.error.is_some());
+    }
+
+    #[test]
+    fn electrum_unreachable() {
+        let port = {
+            let server = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            server.local_addr().unwrap().port()
+        };
+        let probe = probe_electrum(ElectrumConfig {
+            url: "127.0.0.1".to_string(),
+            port,
+            network: Network::Regtest,
+        });
+        assert!(!probe.connected);
+        assert!(probe.error.is_some());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use joinstr::miniscript::bitcoin::{
+    hashes::{sha256, Hash},
+    hex::DisplayHex,
+    secp256k1::{Keypair, Message, Secp256k1, SecretKey},
+};
+use serde_json::{json, Value};
+use tungstenite::{
+    client_tls_with_config, http::Uri, stream::MaybeTlsStream, Message as WsMessage, WebSocket,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Generate a throwaway Nostr key.
+pub(crate) fn ephemeral_key() -> SecretKey {
+    loop {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        if let Ok(key) = SecretKey::from_slice(&bytes) {
+            return key;
+        }
+    }
+}
+
+/// Build and sign a Nostr event as described in NIP-01.
+pub(crate) fn sign_event(key: &SecretKey, kind: u64, tags: Value, content: &str) -> Value {
+    let secp = Secp256k1::new();
+    let keypair = Keypair::from_secret_key(&secp, key);
+    let pubkey = keypair
+        .x_only_public_key()
+        .0
+        .serialize()
+        .to_lower_hex_string();
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("after epoch")
+        .as_secs();
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    let id = sha256::Hash::hash(serialized.as_bytes()).to_byte_array();
+    let sig = secp.sign_schnorr_no_aux_rand(&Message::from_digest(id), &keypair);
+    json!({
+        "id": id.to_lower_hex_string(),
+        "pubkey": pubkey,
+        "created_at": created_at,
+        "kind": kind,
+        "tags": tags,
+        "content": content,
+        "sig": sig.serialize().to_lower_hex_string(),
+    })
+}
+
+/// Kind of the NIP-42 authentication event.
+const AUTH_KIND: u64 = 22_242;
+
+/// Minimal blocking connection to a Nostr relay, it only covers the few
+/// messages the joinstr core does not expose. The NIP-42 AUTH challenges of
+/// the relay are answered with `auth_key`.
+pub(crate) struct Relay {
+    socket: WebSocket<MaybeTlsStream<TcpStream>>,
+    url: String,
+    auth_key: SecretKey,
+    challenge: Option<String>,
+    authenticated: bool,
+}
+
+impl Relay {
+    pub fn connect(url: &str, auth_key: SecretKey) -> Result<Self, String> {
+        let uri: Uri = url.parse().map_err(|e| format!("{e}"))?;
+        let host = uri.host().ok_or("relay url without host".to_string())?;
+        let port = match (uri.port_u16(), uri.scheme_str()) {
+            (Some(port), _) => port,
+            (None, Some("wss")) => 443,
+            (None, Some("ws")) => 80,
+            _ => return Err("relay url must start with ws:// or wss://".to_string()),
+        };
+        let addr = (host, port)
+            .to_socket_addrs()
+            .map_err(|e| format!("{e}"))?
+            .next()
+            .ok_or("cannot resolve the relay host".to_string())?;
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| format!("{e}"))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+        stream
+            .set_write_timeout(Some(TIMEOUT))
+            .map_err(|e| format!("{e}"))?;
+        let (socket, _) =
+            client_tls_with_config(url, stream, None, None).map_err(|e| format!("{e}"))?;
+        Ok(Self {
+            socket,
+            url: url.to_string(),
+            auth_key,
+            challenge: None,
+            authenticated: false,
+        })
+    }
+
+    pub fn send(&mut self, message: Value) -> Result<(), String> {
+        self.socket
+            .send(WsMessage::Text(message.to_string()))
+            .map_err(|e| format!("{e}"))
+    }
+
+    /// Wait for the next message of the relay, pings are answered by the
+    /// socket itself and AUTH challenges are kept for [`Relay::authenticate`].
+    pub fn recv(&mut self) -> Result<Value, String> {
+        loop {
+            match self.socket.read().map_err(|e| format!("{e}"))? {
+                WsMessage::Text(text) => {
+                    let message: Value = serde_json::from_str(&text).map_err(|e| format!("{e}"))?;
+                    if message.get(0).and_then(Value::as_str) == Some("AUTH") {
+                        self.challenge = message.get(1).and_then(Value::as_str).map(String::from);
+                        continue;
+                    }
+                    return Ok(message);
+                }
+                WsMessage::Close(_) => return Err("relay closed the connection".to_string()),
+                _ => continue,
+            }
+        }
+    }
+
+    pub fn authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Answer the last AUTH challenge of the relay, returns whether the
+    /// relay accepted it.
+    pub fn authenticate(&mut self) -> Result<bool, String> {
+        let Some(challenge) = self.challenge.take() else {
+            return Ok(false);
+        };
+        let tags = json!([["relay", self.url], ["challenge", challenge]]);
+        let event = sign_event(&self.auth_key, AUTH_KIND, tags, "");
+        let (accepted, _) = self.wait_ok(json!(["AUTH", event]), &event)?;
+        self.authenticated = accepted;
+        Ok(accepted)
+    }
+
+    /// Publish `event` and wait for the relay to accept or reject it,
+    /// returns the acceptance and the relay message. An event rejected
+    /// because we are not authenticated is sent again after authenticating.
+    pub fn publish(&mut self, event: &Value) -> Result<(bool, String), String> {
+        let (accepted, reason) = self.wait_ok(json!(["EVENT", event]), event)?;
+        if !accepted && reason.starts_with("auth-required:") && self.authenticate()? {
+            return self.wait_ok(json!(["EVENT", event]), event);
+        }
+        Ok((accepted, reason))
+    }
+
+    fn wait_ok(&mut self, message: Value, event: &Value) -> Result<(bool, String), String> {
+        self.send(message)?;
+        loop {
+            let message = self.recv()?;
+            if message.get(0).and_then(Value::as_str) == Some("OK")
+                && message.get(1) == event.get("id")
+            {
+                let accepted = message.get(2).and_then(Value::as_bool).unwrap_or(false);
+                let reason = message.get(3).and_then(Value::as_str).unwrap_or_default();
+                return Ok((accepted, reason.to_string()));
+            }
+        }
+    }
+
+    pub fn close(mut self) {
+        let _ = self.socket.close(None);
+    }
+}
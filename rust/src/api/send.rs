@@ -0,0 +1,427 @@
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::{
+    self,
+    absolute::LockTime,
+    bip32::Xpriv,
+    ecdsa,
+    hashes::Hash,
+    secp256k1::{Message, Secp256k1},
+    sighash::{EcdsaSighashType, SighashCache},
+    transaction::Version,
+    Amount, CompressedPublicKey, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, WPubkeyHash,
+    Witness,
+};
+
+use super::{
+    derivation, electrum,
+    joinstr::{Address, Coin, Mnemonic, Network},
+    verify::WPKH_WITNESS_VSIZE,
+};
+
+pub struct Recipient {
+    pub address: Address,
+    pub amount_sat: u64,
+}
+
+pub struct SendRequest {
+    pub mnemonics: Mnemonic,
+    pub electrum_url: String,
+    pub electrum_port: u16,
+    pub network: Network,
+    /// All these coins are spent.
+    pub inputs: Vec<Coin>,
+    pub recipients: Vec<Recipient>,
+    /// Fee rate in sat/vb
+    pub fee_rate: f64,
+    /// Send even if the inputs raise privacy warnings.
+    pub ignore_warnings: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SendReport {
+    pub txid: String,
+    pub fee_sat: u64,
+    /// Amount sent back to a fresh address of the wallet, 0 if the change
+    /// was below the dust limit and added to the fee.
+    pub change_sat: u64,
+    pub warnings: Vec<String>,
+}
+
+/// Privacy warnings raised by spending `inputs` together.
+#[frb(sync)]
+pub fn spend_warnings(inputs: Vec<Coin>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let postmix = inputs.iter().filter(|c| c.is_postmix()).count();
+    if postmix > 0 && postmix < inputs.len() {
+        warnings.push("premix and postmix coins are spent together".to_string());
+    }
+    if postmix > 1 {
+        warnings.push("several postmix coins are linked together".to_string());
+    }
+    if inputs.iter().any(|c| c.is_frozen()) {
+        warnings.push("frozen coins are spent".to_string());
+    }
+    warnings
+}
+
+fn vsize(tx: &Transaction) -> u64 {
+    tx.vsize() as u64 + WPKH_WITNESS_VSIZE * tx.input.len() as u64
+}
+
+fn fee(tx: &Transaction, fee_rate: f64) -> u64 {
+    (vsize(tx) as f64 * fee_rate).ceil() as u64
+}
+
+/// Build the unsigned transaction, returns it with its fee & change.
+fn build(
+    request: &SendRequest,
+    change: impl FnOnce() -> Result<ScriptBuf, String>,
+) -> Result<(Transaction, u64, u64), String> {
+    if request.inputs.is_empty() {
+        return Err("no input".to_string());
+    }
+    if request.recipients.is_empty() {
+        return Err("no recipient".to_string());
+    }
+    if !request.fee_rate.is_finite() || request.fee_rate < 1.0 {
+        return Err("fee rate must be at least 1 sat/vb".to_string());
+    }
+    let mut output = Vec::new();
+    for recipient in &request.recipients {
+        if !recipient.address.is_valid_for_network(request.network) {
+            return Err(format!(
+                "{} is not valid for {:?}",
+                recipient.address.as_string(),
+                request.network
+            ));
+        }
+        let script_pubkey = recipient
+            .address
+            .as_unchecked()
+            .assume_checked_ref()
+            .script_pubkey();
+        let dust = script_pubkey.minimal_non_dust().to_sat();
+        if recipient.amount_sat < dust {
+            return Err(format!(
+                "{} sats to {} is below the dust limit of {dust} sats",
+                recipient.amount_sat,
+                recipient.address.as_string()
+            ));
+        }
+        output.push(TxOut {
+            value: Amount::from_sat(recipient.amount_sat),
+            script_pubkey,
+        });
+    }
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: request
+            .inputs
+            .iter()
+            .map(|c| TxIn {
+                previous_output: c.outpoint_inner(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output,
+    };
+
+    let overflow = || "amount overflow".to_string();
+    let inputs = request
+        .inputs
+        .iter()
+        .try_fold(0u64, |sum, c| sum.checked_add(c.amount_sat()))
+        .ok_or_else(overflow)?;
+    let outputs = request
+        .recipients
+        .iter()
+        .try_fold(0u64, |sum, r| sum.checked_add(r.amount_sat))
+        .ok_or_else(overflow)?;
+    let needed = outputs
+        .checked_add(fee(&tx, request.fee_rate))
+        .ok_or_else(overflow)?;
+    if inputs < needed {
+        return Err(format!(
+            "insufficient funds: {inputs} sats available, {needed} sats needed"
+        ));
+    }
+
+    // size the change output with a placeholder P2WPKH script so we only
+    // reserve a change address if it is actually needed
+    let placeholder = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+    let mut with_change = tx.clone();
+    with_change.output.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: placeholder.clone(),
+    });
+    let fee_with_change = fee(&with_change, request.fee_rate);
+    let change_value = outputs
+        .checked_add(fee_with_change)
+        .map(|spent| inputs.saturating_sub(spent))
+        .ok_or_else(overflow)?;
+    if change_value < placeholder.minimal_non_dust().to_sat() {
+        return Ok((tx, inputs - outputs, 0));
+    }
+    let last = with_change.output.last_mut().expect("pushed above");
+    last.value = Amount::from_sat(change_value);
+    last.script_pubkey = change()?;
+    Ok((with_change, fee_with_change, change_value))
+}
+
+/// Sign our P2WPKH inputs with the keys derived from the seed at the BIP84
+/// path of each coin: `m/84'/<coin type>'/0'/<depth>/<index>`.
+fn sign(request: &SendRequest, tx: Transaction) -> Result<Transaction, String> {
+    let network: bitcoin::Network = request.network.into();
+    let secp = Secp256k1::new();
+    let master =
+        Xpriv::new_master(network, &*request.mnemonics.seed()?).map_err(|e| format!("{e}"))?;
+    let mut psbt = Psbt::from_unsigned_tx(tx).map_err(|e| format!("{e}"))?;
+    let mut cache = SighashCache::new(psbt.unsigned_tx.clone());
+    for (index, coin) in request.inputs.iter().enumerate() {
+        let coin_path = coin.coin_path();
+        let index_in_chain = coin_path
+            .index
+            .ok_or(format!("coin {} without derivation index", coin.outpoint()))?;
+        let path = derivation::bip84_path(network, coin_path.depth, index_in_chain);
+        let key = master
+            .derive_priv(&secp, &path)
+            .map_err(|e| format!("{e}"))?
+            .to_priv();
+        let pubkey =
+            CompressedPublicKey::from_private_key(&secp, &key).map_err(|e| format!("{e}"))?;
+        let script = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash());
+        if script != coin.script_pubkey() {
+            return Err(format!(
+                "coin {} does not belong to the wallet",
+                coin.outpoint()
+            ));
+        }
+
+        let sighash = cache
+            .p2wpkh_signature_hash(index, &script, coin.txout().value, EcdsaSighashType::All)
+            .map_err(|e| format!("{e}"))?;
+        let signature = ecdsa::Signature::sighash_all(
+            secp.sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), &key.inner),
+        );
+        let input = &mut psbt.inputs[index];
+        input.witness_utxo = Some(coin.txout());
+        input
+            .bip32_derivation
+            .insert(pubkey.0, (master.fingerprint(&secp), path));
+        input.final_script_witness = Some(Witness::p2wpkh(&signature, &pubkey.0));
+    }
+    Ok(psbt.extract_tx_unchecked_fee_rate())
+}
+
+/// Send `request.inputs` to the recipients, the change goes to a fresh
+/// receive address. Fails if the inputs raise privacy warnings unless
+/// `request.ignore_warnings` is set.
+#[frb(sync)]
+pub fn send(request: SendRequest) -> SendResult {
+    let mut res = SendResult::new();
+    let warnings = spend_warnings(request.inputs.clone());
+    if !warnings.is_empty() && !request.ignore_warnings {
+        res.set_error(warnings.join(", "));
+        return res;
+    }
+
+    let change = || {
+        let mut client = electrum::Client::new(&request.electrum_url, request.electrum_port)?;
+        derivation::next_receive_address(&request.mnemonics, request.network, &mut client)
+            .map(|a| a.as_unchecked().assume_checked_ref().script_pubkey())
+    };
+    let result = build(&request, change)
+        .and_then(|(tx, fee, change)| Ok((sign(&request, tx)?, fee, change)))
+        .and_then(|(tx, fee, change)| {
+            let mut client = electrum::Client::new(&request.electrum_url, request.electrum_port)?;
+            let txid = client.broadcast(&tx)?;
+            Ok(SendReport {
+                txid: txid.to_string(),
+                fee_sat: fee,
+                change_sat: change,
+                warnings,
+            })
+        });
+    match result {
+        Ok(report) => res.set(report),
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+#[frb(opaque)]
+pub struct SendResult {
+    report: Option<SendReport>,
+    error: Option<String>,
+}
+
+impl SendResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            report: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.report.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.report.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<SendReport> {
+        self.report.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: SendReport) {
+        self.report = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{OutPoint, Txid};
+
+    use super::*;
+    use crate::test_support::{coin, script};
+
+    const PHRASE: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    /// BIP84 test vector, first receive address of `PHRASE`.
+    const FIRST_ADDRESS: &str = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+
+    fn request(inputs: Vec<Coin>, amount_sat: u64, fee_rate: f64) -> SendRequest {
+        SendRequest {
+            mnemonics: Mnemonic::from_string(PHRASE.to_string()).unwrap(),
+            electrum_url: String::new(),
+            electrum_port: 0,
+            network: Network::Bitcoin,
+            inputs,
+            recipients: vec![Recipient {
+                address: Address::from_string(
+                    "bc1qm34lsc65zpw79lxes69zkqmk6ee3ewf0j77s3h".to_string(),
+                )
+                .unwrap(),
+                amount_sat,
+            }],
+            fee_rate,
+            ignore_warnings: false,
+        }
+    }
+
+    fn wallet_coin(value: u64) -> Coin {
+        let address = Address::from_string(FIRST_ADDRESS.to_string()).unwrap();
+        let script = address.as_unchecked().assume_checked_ref().script_pubkey();
+        coin(OutPoint::new(Txid::all_zeros(), 0), script, value)
+    }
+
+    #[test]
+    fn change_output() {
+        let request = request(vec![wallet_coin(100_000)], 50_000, 1.0);
+        let (tx, fee, change) = build(&request, || Ok(script(1))).unwrap();
+        // 1 input, 2 P2WPKH outputs: 140 vbytes once signed
+        assert_eq!(fee, 140);
+        assert_eq!(change, 100_000 - 50_000 - 140);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].script_pubkey, script(1));
+        assert_eq!(tx.output[1].value.to_sat(), change);
+    }
+
+    #[test]
+    fn dust_change_added_to_fee() {
+        // 169 sats of change would be left, below the P2WPKH dust limit
+        let request = request(vec![wallet_coin(50_000 + 140 + 169)], 50_000, 1.0);
+        let (tx, fee, change) =
+            build(&request, || Err("no change address expected".to_string())).unwrap();
+        assert_eq!(change, 0);
+        assert_eq!(fee, 140 + 169);
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[test]
+    fn dust_recipient_refused() {
+        for amount in [0, 293] {
+            let request = request(vec![wallet_coin(100_000)], amount, 1.0);
+            let error = build(&request, || Ok(script(1))).err().unwrap();
+            assert!(
+                error.contains("below the dust limit of 294 sats"),
+                "{error}"
+            );
+        }
+        let request = request(vec![wallet_coin(100_000)], 294, 1.0);
+        assert!(build(&request, || Ok(script(1))).is_ok());
+    }
+
+    #[test]
+    fn insufficient_funds() {
+        let request = request(vec![wallet_coin(50_100)], 50_000, 1.0);
+        let error = build(&request, || Ok(script(1))).err().unwrap();
+        assert!(error.starts_with("insufficient funds"), "{error}");
+    }
+
+    #[test]
+    fn sign_wallet_input() {
+        let request = request(vec![wallet_coin(100_000)], 50_000, 2.0);
+        let (tx, _, _) = build(&request, || Ok(script(1))).unwrap();
+        let signed = sign(&request, tx).unwrap();
+
+        let witness = &signed.input[0].witness;
+        assert_eq!(witness.len(), 2);
+        let signature = ecdsa::Signature::from_slice(&witness[0]).unwrap();
+        let pubkey = CompressedPublicKey::from_slice(&witness[1]).unwrap();
+        let script = request.inputs[0].script_pubkey();
+        assert_eq!(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()), script);
+        let sighash = SighashCache::new(&signed)
+            .p2wpkh_signature_hash(0, &script, Amount::from_sat(100_000), EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        Secp256k1::new()
+            .verify_ecdsa(&message, &signature.signature, &pubkey.0)
+            .unwrap();
+    }
+
+    #[test]
+    fn refuse_foreign_input() {
+        let foreign = coin(OutPoint::new(Txid::all_zeros(), 0), script(7), 100_000);
+        let request = request(vec![foreign], 50_000, 2.0);
+        let (tx, _, _) = build(&request, || Ok(script(1))).unwrap();
+        assert!(sign(&request, tx).is_err());
+    }
+
+    #[test]
+    fn invalid_fee_rate() {
+        for fee_rate in [0.5, f64::NAN, f64::INFINITY, -1.0] {
+            let request = request(vec![wallet_coin(100_000)], 50_000, fee_rate);
+            assert!(build(&request, || Ok(script(1))).is_err());
+        }
+    }
+
+    #[test]
+    fn amount_overflow() {
+        let inputs = vec![wallet_coin(u64::MAX), wallet_coin(1)];
+        let request = request(inputs, 50_000, 1.0);
+        let error = build(&request, || Ok(script(1))).err();
+        assert_eq!(error.as_deref(), Some("amount overflow"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flutter_rust_bridge::frb;
+use joinstr::{
+    joinstr::Joinstr,
+    miniscript::bitcoin::{self, Psbt},
+    signer::{self, JoinstrSigner},
+};
+
+use super::{
+    joinstr::{Address, Coin, EmptyResult, Network, Pool},
+    labels, mix,
+    verify::{verify, Expected, VerificationReport},
+};
+
+/// Peer configuration for a coinjoin signed outside of the library, e.g.
+/// by a hardware wallet: it carries no mnemonic.
+pub struct ExternalPeerConfig {
+    pub electrum_url: String,
+    pub electrum_port: u16,
+    pub input: Coin,
+    pub output: Address,
+    pub relay: String,
+    pub network: Network,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningState {
+    /// Waiting for the other peers.
+    Running,
+    /// The unsigned PSBT is available and waits for a signature.
+    WaitingSignature,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct SigningStatus {
+    pub state: SigningState,
+    pub txid: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Session {
+    unsigned: Option<Psbt>,
+    report: Option<VerificationReport>,
+    signed: Option<Psbt>,
+    aborted: bool,
+    txid: Option<String>,
+    error: Option<String>,
+}
+
+type Shared = Arc<(Mutex<Session>, Condvar)>;
+
+/// Signer handed to the core, it publishes the PSBT to sign and blocks
+/// until the app submits the signed one, at most until `deadline` (unix
+/// timestamp, the pool timeout).
+struct ExternalSigner {
+    session: Shared,
+    expected: Expected,
+    deadline: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl JoinstrSigner for ExternalSigner {
+    fn sign_input(&self, psbt: &mut Psbt, input_index: usize) -> Result<(), signer::Error> {
+        let (lock, cvar) = &*self.session;
+        let mut session = lock.lock().expect("poisoned");
+        session.report = Some(verify(psbt, &self.expected));
+        session.unsigned = Some(psbt.clone());
+        session.signed = None;
+        cvar.notify_all();
+        while session.signed.is_none() && !session.aborted {
+            let remaining = self.deadline.saturating_sub(now());
+            if remaining == 0 {
+                session.unsigned = None;
+                session.report = None;
+                return Err(signer::Error::Signer("signing timed out".to_string()));
+            }
+            session = cvar
+                .wait_timeout(session, Duration::from_secs(remaining))
+                .expect("poisoned")
+                .0;
+        }
+        if session.aborted {
+            session.unsigned = None;
+            session.report = None;
+            return Err(signer::Error::Signer("signing aborted".to_string()));
+        }
+        let signed = session.signed.take().expect("checked above");
+        session.unsigned = None;
+        session.report = None;
+
+        let input = signed
+            .inputs
+            .get(input_index)
+            .ok_or(signer::Error::Signer("missing input".to_string()))?;
+        if input.partial_sigs.is_empty() && input.final_script_witness.is_none() {
+            return Err(signer::Error::Signer("input not signed".to_string()));
+        }
+        psbt.inputs[input_index] = input.clone();
+        Ok(())
+    }
+}
+
+fn abort(session: &Shared) {
+    let (lock, cvar) = &**session;
+    lock.lock().expect("poisoned").aborted = true;
+    cvar.notify_all();
+}
+
+/// Aborts the session once the last [`SigningSession`] handle is dropped,
+/// nobody is left to submit a signature.
+struct Handle(Shared);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        abort(&self.0);
+    }
+}
+
+/// A coinjoin in progress whose input is signed by an external signer.
+/// Dropping every handle aborts it.
+#[frb(opaque)]
+#[derive(Clone)]
+pub struct SigningSession {
+    #[frb(ignore)]
+    handle: Arc<Handle>,
+}
+
+impl SigningSession {
+    fn session(&self) -> &Shared {
+        &self.handle.0
+    }
+
+    /// Join `pool` in the background, the session pauses when our input
+    /// must be signed, see [`SigningSession::unsigned_psbt`].
+    #[frb(sync)]
+    pub fn join(pool: Pool, peer: ExternalPeerConfig) -> SigningSession {
+        let session: Shared = Arc::new((Mutex::new(Session::default()), Condvar::new()));
+        let signing = SigningSession {
+            handle: Arc::new(Handle(session.clone())),
+        };
+        let input_frozen = labels::is_frozen(&peer.input.outpoint());
+        thread::spawn(move || {
+            let result = if input_frozen {
+                Err("input coin is frozen".to_string())
+            } else {
+                Expected::new(&pool, &peer.input, &peer.output).and_then(|expected| {
+                    let signer = ExternalSigner {
+                        session: session.clone(),
+                        expected,
+                        deadline: pool.timeout().ok_or("pool has no timeout".to_string())?,
+                    };
+                    join_pool(
+                        pool,
+                        &peer.relay,
+                        (&peer.electrum_url, peer.electrum_port),
+                        peer.input,
+                        peer.output,
+                        peer.network.into(),
+                        &signer,
+                    )
+                })
+            };
+            let (lock, cvar) = &*session;
+            let mut session = lock.lock().expect("poisoned");
+            match result {
+                Ok(txid) => session.txid = Some(txid),
+                Err(e) => session.error = Some(e),
+            }
+            cvar.notify_all();
+        });
+        signing
+    }
+
+    /// The unsigned coinjoin PSBT (base64), available once all peers have
+    /// registered.
+    #[frb(sync)]
+    pub fn unsigned_psbt(&self) -> Option<String> {
+        let session = self.session().0.lock().expect("poisoned");
+        session.unsigned.as_ref().map(|p| p.to_string())
+    }
+
+    /// Verification report of the unsigned PSBT, it must pass before the
+    /// signed PSBT is accepted unless explicitly overridden.
+    #[frb(sync)]
+    pub fn report(&self) -> Option<VerificationReport> {
+        let session = self.session().0.lock().expect("poisoned");
+        session.report.clone()
+    }
+
+    /// Provide the PSBT (base64) signed by the external signer, it must
+    /// spend the same transaction as the unsigned one. If the verification
+    /// report failed, the PSBT is refused unless `force` is set.
+    #[frb(sync)]
+    pub fn submit_psbt(&self, psbt: String, force: bool) -> EmptyResult {
+        let mut res = EmptyResult::new();
+        let signed = match Psbt::from_str(&psbt) {
+            Ok(p) => p,
+            Err(e) => {
+                res.set_error(format!("{e}"));
+                return res;
+            }
+        };
+        let (lock, cvar) = &**self.session();
+        let mut session = lock.lock().expect("poisoned");
+        let passed = session.report.as_ref().map(|r| r.passed()).unwrap_or(false);
+        match &session.unsigned {
+            Some(_) if !passed && !force => {
+                res.set_error("verification report failed".to_string());
+            }
+            Some(unsigned) if unsigned.unsigned_tx == signed.unsigned_tx => {
+                session.signed = Some(signed);
+                cvar.notify_all();
+            }
+            Some(_) => res.set_error("PSBT does not match the coinjoin transaction".to_string()),
+            None => res.set_error("no PSBT waiting for signature".to_string()),
+        }
+
+        res
+    }
+
+    /// Refuse to sign, the coinjoin fails.
+    #[frb(sync)]
+    pub fn abort(&self) {
+        abort(self.session());
+    }
+
+    #[frb(sync)]
+    pub fn status(&self) -> SigningStatus {
+        let session = self.session().0.lock().expect("poisoned");
+        let state = if session.txid.is_some() {
+            SigningState::Done
+        } else if session.error.is_some() {
+            SigningState::Failed
+        } else if session.unsigned.is_some() {
+            SigningState::WaitingSignature
+        } else {
+            SigningState::Running
+        };
+        SigningStatus {
+            state,
+            txid: session.txid.clone(),
+            error: session.error.clone(),
+        }
+    }
+}
+
+/// Join `pool` with `input`, our input is signed by `signer`.
+pub(crate) fn join_pool<S: JoinstrSigner>(
+    pool: Pool,
+    relay: &str,
+    electrum: (&str, u16),
+    input: Coin,
+    output: Address,
+    network: bitcoin::Network,
+    signer: &S,
+) -> Result<String, String> {
+    mix::check_history()?;
+    let depth = input.mix_depth();
+    let pool: joinstr::nostr::Pool = pool.into();
+    let mut joinstr = Joinstr::new_peer(
+        relay,
+        &pool,
+        electrum,
+        input.into(),
+        output.into(),
+        network,
+        "peer",
+    )
+    .map_err(|e| format!("{e}"))?;
+    joinstr
+        .start_coinjoin(Some(pool), Some(signer))
+        .map_err(|e| format!("{e}"))?;
+    let txid = joinstr
+        .final_tx()
+        .map(|tx| tx.compute_txid().to_string())
+        .ok_or("no final transaction".to_string())?;
+    mix::record_coinjoin(&txid, depth)?;
+    Ok(txid)
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, Amount, OutPoint, Txid, Witness};
+
+    use super::*;
+    use crate::test_support::{script, transaction};
+
+    fn expected() -> Expected {
+        Expected {
+            input: OutPoint::new(Txid::all_zeros(), 0),
+            input_script: script(1),
+            output_script: script(2),
+            denomination: Amount::from_sat(100_000),
+            peers: 2,
+            fee: 10,
+        }
+    }
+
+    fn psbt() -> Psbt {
+        let tx = transaction(
+            &[OutPoint::new(Txid::all_zeros(), 0)],
+            &[(script(2), 99_000)],
+        );
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    /// Run the signer in the background, returns once the PSBT is published.
+    fn signing(deadline: u64) -> (SigningSession, thread::JoinHandle<Result<Psbt, String>>) {
+        let session: Shared = Arc::new((Mutex::new(Session::default()), Condvar::new()));
+        let signer = ExternalSigner {
+            session: session.clone(),
+            expected: expected(),
+            deadline,
+        };
+        let handle = thread::spawn(move || {
+            let mut psbt = psbt();
+            signer
+                .sign_input(&mut psbt, 0)
+                .map(|_| psbt)
+                .map_err(|e| format!("{e:?}"))
+        });
+        let signing = SigningSession {
+            handle: Arc::new(Handle(session)),
+        };
+        while signing.unsigned_psbt().is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        (signing, handle)
+    }
+
+    #[test]
+    fn submitted_signature_used() {
+        let (signing, handle) = signing(now() + 60);
+        assert_eq!(signing.status().state, SigningState::WaitingSignature);
+
+        let mut signed = psbt();
+        signed.inputs[0].final_script_witness = Some(Witness::from_slice(&[[1u8; 64]]));
+        assert!(signing.submit_psbt(signed.to_string(), true).is_ok());
+        let psbt = handle.join().unwrap().unwrap();
+        assert!(psbt.inputs[0].final_script_witness.is_some());
+    }
+
+    #[test]
+    fn signing_times_out() {
+        let (signing, handle) = signing(now() + 1);
+        let error = handle.join().unwrap().unwrap_err();
+        assert!(error.contains("signing timed out"), "{error}");
+        assert!(signing.unsigned_psbt().is_none());
+    }
+
+    #[test]
+    fn last_handle_dropped_aborts() {
+        let (signing, handle) = signing(now() + 60);
+        let other = signing.clone();
+        drop(signing);
+        assert_eq!(other.status().state, SigningState::WaitingSignature);
+        drop(other);
+        let error = handle.join().unwrap().unwrap_err();
+        assert!(error.contains("signing aborted"), "{error}");
+    }
+}
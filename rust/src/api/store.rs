@@ -0,0 +1,107 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+
+use super::{coordinator::CoordinatorRecord, joinstr::EmptyResult, labels::Label};
+
+const STATE_FILE: &str = "dart_joinstr.json";
+
+static DATA_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Local state persisted between sessions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct State {
+    /// txid of the coinjoins we took part in => mix depth of their outputs
+    #[serde(default)]
+    pub coinjoins: BTreeMap<String, u32>,
+    /// BIP329 labels, frozen coins are `output` records with `spendable: false`
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    /// Pools we host as initiator
+    #[serde(default)]
+    pub coordinators: Vec<CoordinatorRecord>,
+    /// "<fingerprint>/<network>" => next receive index to derive
+    #[serde(default)]
+    pub address_indexes: BTreeMap<String, u32>,
+    /// (day, fees in sats spent by automix during this day)
+    #[serde(default)]
+    pub automix_fees: (u64, u64),
+}
+
+fn state_path(dir: &Option<PathBuf>) -> Result<PathBuf, String> {
+    dir.as_ref()
+        .map(|d| d.join(STATE_FILE))
+        .ok_or("data directory not set".to_string())
+}
+
+fn read(path: &PathBuf) -> Result<State, String> {
+    if !path.exists() {
+        return Ok(State::default());
+    }
+    let content = fs::read_to_string(path).map_err(|e| format!("{e}"))?;
+    serde_json::from_str(&content).map_err(|e| format!("{e}"))
+}
+
+/// Write `content` to a temp file then move it to `path`, so a crash never
+/// leaves a truncated file. On unix the file is only readable by its owner.
+pub(crate) fn write_atomic(path: &Path, content: &str) -> Result<(), String> {
+    let tmp = path.with_extension("tmp");
+    // a leftover temp file would keep its permissions
+    let _ = fs::remove_file(&tmp);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp).map_err(|e| format!("{e}"))?;
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("{e}"))?;
+    fs::rename(&tmp, path).map_err(|e| format!("{e}"))
+}
+
+fn write(path: &Path, state: &State) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(state).map_err(|e| format!("{e}"))?;
+    write_atomic(path, &content)
+}
+
+/// Returns a copy of the persisted state.
+pub(crate) fn load() -> Result<State, String> {
+    let dir = DATA_DIR.lock().expect("poisoned");
+    read(&state_path(&dir)?)
+}
+
+/// Apply `f` to the persisted state and write it back.
+pub(crate) fn update<R>(f: impl FnOnce(&mut State) -> R) -> Result<R, String> {
+    let dir = DATA_DIR.lock().expect("poisoned");
+    let path = state_path(&dir)?;
+    let mut state = read(&path)?;
+    let ret = f(&mut state);
+    write(&path, &state)?;
+    Ok(ret)
+}
+
+/// Set the directory where the library persists its local state, the app
+/// is expected to call this once at startup with a private writable path.
+#[frb(sync)]
+pub fn set_data_dir(path: String) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    let path = PathBuf::from(path);
+    if let Err(e) = fs::create_dir_all(&path) {
+        res.set_error(format!("{e}"));
+        return res;
+    }
+    if let Err(e) = read(&path.join(STATE_FILE)) {
+        res.set_error(e);
+        return res;
+    }
+    *DATA_DIR.lock().expect("poisoned") = Some(path);
+
+    res
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use flutter_rust_bridge::frb;
+
+use super::{
+    electrum,
+    joinstr::{list_mnemonic_coins, Coin, Mnemonic, Network},
+};
+
+#[derive(Debug, Clone)]
+pub struct DenominationBucket {
+    pub amount_sat: u64,
+    pub count: usize,
+    pub total_sat: u64,
+}
+
+/// Balances of the wallet, frozen coins are only counted in `frozen_sat`
+/// so `premix_sat + postmix_sat + frozen_sat` is the total balance.
+#[derive(Debug, Clone, Default)]
+pub struct WalletSummary {
+    pub confirmed_sat: u64,
+    pub unconfirmed_sat: u64,
+    pub premix_sat: u64,
+    pub postmix_sat: u64,
+    pub frozen_sat: u64,
+    /// Postmix coins grouped by amount.
+    pub buckets: Vec<DenominationBucket>,
+}
+
+fn summarize(coins: &[Coin], unconfirmed: &BTreeSet<String>) -> WalletSummary {
+    let mut summary = WalletSummary::default();
+    let mut buckets: BTreeMap<u64, DenominationBucket> = BTreeMap::new();
+    for coin in coins {
+        let amount = coin.amount_sat();
+        if unconfirmed.contains(&coin.outpoint()) {
+            summary.unconfirmed_sat += amount;
+        } else {
+            summary.confirmed_sat += amount;
+        }
+        if coin.is_frozen() {
+            summary.frozen_sat += amount;
+        } else if coin.is_postmix() {
+            summary.postmix_sat += amount;
+            let bucket = buckets.entry(amount).or_insert(DenominationBucket {
+                amount_sat: amount,
+                count: 0,
+                total_sat: 0,
+            });
+            bucket.count += 1;
+            bucket.total_sat += amount;
+        } else {
+            summary.premix_sat += amount;
+        }
+    }
+    summary.buckets = buckets.into_values().collect();
+    summary
+}
+
+/// Compute the wallet balances from the coins returned by `list_mnemonic_coins`.
+#[frb(sync)]
+pub fn wallet_summary(
+    mnemonics: &Mnemonic,
+    electrum_url: String,
+    electrum_port: u16,
+    range: (u32, u32),
+    network: Network,
+) -> WalletSummaryResult {
+    let mut res = WalletSummaryResult::new();
+    let coins = list_mnemonic_coins(
+        mnemonics,
+        electrum_url.clone(),
+        electrum_port,
+        range,
+        network,
+    );
+    let Some(coins) = coins.result() else {
+        res.set_error(coins.error().unwrap_or_default());
+        return res;
+    };
+
+    // the coins do not carry their height, look it up by script
+    let unconfirmed = electrum::Client::new(&electrum_url, electrum_port).and_then(|mut client| {
+        let scripts: BTreeSet<_> = coins.iter().map(|c| c.script_pubkey()).collect();
+        let mut unconfirmed = BTreeSet::new();
+        for script in scripts {
+            for (outpoint, height) in client.script_unspent(&script)? {
+                if height <= 0 {
+                    unconfirmed.insert(outpoint.to_string());
+                }
+            }
+        }
+        Ok(unconfirmed)
+    });
+    match unconfirmed {
+        Ok(unconfirmed) => res.set(summarize(&coins, &unconfirmed)),
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+#[frb(opaque)]
+pub struct WalletSummaryResult {
+    summary: Option<WalletSummary>,
+    error: Option<String>,
+}
+
+impl WalletSummaryResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            summary: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.summary.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.summary.is_some() && !self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn result(&self) -> Option<WalletSummary> {
+        self.summary.clone()
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: WalletSummary) {
+        self.summary = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::{
+        miniscript::bitcoin::{self, hashes::Hash, OutPoint, Txid},
+        signer,
+    };
+
+    use super::*;
+    use crate::{
+        api::labels,
+        test_support::{coin, data_dir, mnemonic, script, transaction, MockElectrum},
+    };
+
+    #[test]
+    fn balances_and_buckets() {
+        let outpoint = |vout| OutPoint::new(Txid::all_zeros(), vout);
+        let premix = coin(outpoint(0), script(1), 30_000);
+        let mut postmix = coin(outpoint(1), script(2), 100_000);
+        postmix.set_mix_depth(1);
+        let mut unconfirmed = coin(outpoint(2), script(3), 100_000);
+        unconfirmed.set_mix_depth(2);
+        let mut frozen = coin(outpoint(3), script(4), 5_000);
+        frozen.set_frozen(true);
+
+        let pending = BTreeSet::from([unconfirmed.outpoint()]);
+        let summary = summarize(&[premix, postmix, unconfirmed, frozen], &pending);
+        assert_eq!(summary.confirmed_sat, 135_000);
+        assert_eq!(summary.unconfirmed_sat, 100_000);
+        assert_eq!(summary.premix_sat, 30_000);
+        assert_eq!(summary.postmix_sat, 200_000);
+        assert_eq!(summary.frozen_sat, 5_000);
+        assert_eq!(summary.buckets.len(), 1);
+        assert_eq!(summary.buckets[0].amount_sat, 100_000);
+        assert_eq!(summary.buckets[0].count, 2);
+        assert_eq!(summary.buckets[0].total_sat, 200_000);
+    }
+
+    #[test]
+    fn summary_from_server() {
+        let _dir = data_dir();
+        let electrum = MockElectrum::start();
+        let signer = signer::WpkhHotSigner::new_from_mnemonics(
+            bitcoin::Network::Regtest,
+            &mnemonic().phrase().unwrap(),
+        )
+        .unwrap();
+        let outpoint = |vout| OutPoint::new(Txid::all_zeros(), vout);
+        let confirmed = electrum.confirm(transaction(
+            &[outpoint(0)],
+            &[
+                (signer.recv_addr_at(0).script_pubkey(), 40_000),
+                (signer.recv_addr_at(1).script_pubkey(), 5_000),
+            ],
+        ));
+        electrum.add_mempool(transaction(
+            &[outpoint(1)],
+            &[(signer.recv_addr_at(2).script_pubkey(), 20_000)],
+        ));
+        let frozen = OutPoint::new(confirmed, 1).to_string();
+        assert!(labels::freeze_coin(frozen, true).is_ok());
+
+        let res = wallet_summary(
+            &mnemonic(),
+            electrum.url(),
+            electrum.port(),
+            (0, 20),
+            Network::Regtest,
+        );
+        let summary = res.result().unwrap();
+        assert_eq!(summary.confirmed_sat, 45_000);
+        assert_eq!(summary.unconfirmed_sat, 20_000);
+        assert_eq!(summary.premix_sat, 60_000);
+        assert_eq!(summary.frozen_sat, 5_000);
+        assert!(summary.buckets.is_empty());
+
+        let res = wallet_summary(&mnemonic(), electrum.url(), 1, (0, 20), Network::Regtest);
+        assert!(res.is_err());
+    }
+}
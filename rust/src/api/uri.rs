@@ -0,0 +1,79 @@
+//! Helpers for the `joinstr:` URIs used to share pools.
+
+/// Percent-encode the characters that have a meaning in a query string.
+pub(crate) fn encode(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '%' | '&' | '=' | '?' | '#' | ' ' | '+' => out.push_str(&format!("%{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub(crate) fn decode(value: &str) -> Option<String> {
+    let mut out = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Parse the query of an URI starting with `prefix` into (key, value) pairs.
+pub(crate) fn parse(uri: &str, prefix: &str) -> Option<Vec<(String, String)>> {
+    let query = uri.trim().strip_prefix(prefix)?;
+    query
+        .split('&')
+        .map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((key.to_string(), decode(value)?))
+        })
+        .collect()
+}
+
+/// Build an URI from `prefix` and (key, value) pairs.
+pub(crate) fn build(prefix: &str, pairs: &[(&str, &str)]) -> String {
+    let query: Vec<_> = pairs
+        .iter()
+        .map(|(key, value)| format!("{key}={}", encode(value)))
+        .collect();
+    format!("{prefix}{}", query.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let value = "100% a&b=c?d#e f+g é";
+        let encoded = encode(value);
+        assert_eq!(encoded, "100%25%20a%26b%3Dc%3Fd%23e%20f%2Bg%20é");
+        assert_eq!(decode(&encoded).as_deref(), Some(value));
+        assert_eq!(decode("%2"), None);
+        assert_eq!(decode("%zz"), None);
+    }
+
+    #[test]
+    fn build_parse() {
+        let uri = build("joinstr:pool?", &[("id", "a b"), ("relay", "wss://r?x=1")]);
+        assert_eq!(uri, "joinstr:pool?id=a%20b&relay=wss://r%3Fx%3D1");
+        assert_eq!(
+            parse(&uri, "joinstr:pool?"),
+            Some(vec![
+                ("id".to_string(), "a b".to_string()),
+                ("relay".to_string(), "wss://r?x=1".to_string()),
+            ])
+        );
+        assert_eq!(parse(&uri, "bitcoin:"), None);
+        assert_eq!(parse("joinstr:pool?id", "joinstr:pool?"), None);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{fs, path::Path};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use flutter_rust_bridge::frb;
+use joinstr::miniscript::bitcoin::hex::{DisplayHex, FromHex};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::{
+    joinstr::{EmptyResult, Mnemonic},
+    store,
+};
+
+const VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+/// Highest Argon2 costs we accept from a vault file, so a crafted file
+/// cannot make unlocking exhaust the memory or the CPU of the device.
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 4;
+
+/// Content of a vault file, the mnemonic is encrypted with XChaCha20Poly1305
+/// under a key derived from the password with Argon2id.
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    /// Argon2 memory cost in KiB
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+impl VaultFile {
+    /// The header is authenticated along the ciphertext so the KDF
+    /// parameters cannot be downgraded.
+    fn aad(&self) -> Vec<u8> {
+        format!(
+            "dart_joinstr vault v{} {} {} {} {}",
+            self.version, self.m_cost, self.t_cost, self.p_cost, self.salt
+        )
+        .into_bytes()
+    }
+
+    fn key(&self, password: &str) -> Result<Zeroizing<[u8; 32]>, String> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err("vault key derivation too expensive".to_string());
+        }
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| format!("{e}"))?;
+        let salt = Vec::<u8>::from_hex(&self.salt).map_err(|e| format!("{e}"))?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), &salt, &mut *key)
+            .map_err(|e| format!("{e}"))?;
+        Ok(key)
+    }
+}
+
+fn seal(mnemonics: &Mnemonic, password: &str) -> Result<VaultFile, String> {
+    let phrase = mnemonics.phrase()?;
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let params = Params::default();
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut vault = VaultFile {
+        version: VERSION,
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        salt: salt.to_lower_hex_string(),
+        nonce: nonce.to_lower_hex_string(),
+        ciphertext: String::new(),
+    };
+    let key = vault.key(password)?;
+    let cipher = XChaCha20Poly1305::new((&*key).into());
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: phrase.as_bytes(),
+                aad: &vault.aad(),
+            },
+        )
+        .map_err(|_| "encryption failed".to_string())?;
+    vault.ciphertext = ciphertext.to_lower_hex_string();
+    Ok(vault)
+}
+
+fn open(vault: &VaultFile, password: &str) -> Result<Mnemonic, String> {
+    if vault.version != VERSION {
+        return Err(format!("unsupported vault version {}", vault.version));
+    }
+    let key = vault.key(password)?;
+    decrypt(vault, &key)
+}
+
+fn decrypt(vault: &VaultFile, key: &[u8; 32]) -> Result<Mnemonic, String> {
+    let nonce = Vec::<u8>::from_hex(&vault.nonce).map_err(|e| format!("{e}"))?;
+    if nonce.len() != 24 {
+        return Err("invalid nonce".to_string());
+    }
+    let ciphertext = Vec::<u8>::from_hex(&vault.ciphertext).map_err(|e| format!("{e}"))?;
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let phrase = cipher
+        .decrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &vault.aad(),
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| "wrong password or corrupted vault".to_string())?;
+    let phrase = String::from_utf8(phrase.to_vec()).map_err(|e| format!("{e}"))?;
+    Mnemonic::from_string(phrase).ok_or("invalid mnemonic in vault".to_string())
+}
+
+fn read(path: &str) -> Result<VaultFile, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{e}"))?;
+    serde_json::from_str(&content).map_err(|e| format!("{e}"))
+}
+
+fn write(path: &str, vault: &VaultFile) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(vault).map_err(|e| format!("{e}"))?;
+    store::write_atomic(Path::new(path), &content)
+}
+
+/// Whether a vault exists at `path`.
+#[frb(sync)]
+pub fn vault_exists(path: String) -> bool {
+    Path::new(&path).exists()
+}
+
+/// Encrypt `mnemonics` with `password` and store it at `path`, an existing
+/// vault is replaced.
+#[frb(sync)]
+pub fn create_vault(path: String, mnemonics: &Mnemonic, password: String) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    let password = Zeroizing::new(password);
+    if let Err(e) = seal(mnemonics, &password).and_then(|vault| write(&path, &vault)) {
+        res.set_error(e);
+    }
+
+    res
+}
+
+/// Decrypt the vault at `path`.
+#[frb(sync)]
+pub fn unlock_vault(path: String, password: String) -> MnemonicResult {
+    let mut res = MnemonicResult::new();
+    let password = Zeroizing::new(password);
+    match read(&path).and_then(|vault| open(&vault, &password)) {
+        Ok(mnemonics) => res.set(mnemonics),
+        Err(e) => res.set_error(e),
+    }
+
+    res
+}
+
+/// Re-encrypt the vault at `path` under `new_password`.
+#[frb(sync)]
+pub fn change_vault_password(
+    path: String,
+    old_password: String,
+    new_password: String,
+) -> EmptyResult {
+    let mut res = EmptyResult::new();
+    let old_password = Zeroizing::new(old_password);
+    let new_password = Zeroizing::new(new_password);
+    let result = read(&path)
+        .and_then(|vault| open(&vault, &old_password))
+        .and_then(|mnemonics| {
+            let vault = seal(&mnemonics, &new_password);
+            mnemonics.wipe();
+            vault
+        })
+        .and_then(|vault| write(&path, &vault));
+    if let Err(e) = result {
+        res.set_error(e);
+    }
+
+    res
+}
+
+#[frb(opaque)]
+pub struct MnemonicResult {
+    mnemonics: Option<Mnemonic>,
+    error: Option<String>,
+}
+
+impl MnemonicResult {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            mnemonics: None,
+            error: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_ok(&self) -> bool {
+        self.mnemonics.is_some() && self.error.is_none()
+    }
+
+    #[frb(sync)]
+    pub fn is_err(&self) -> bool {
+        !self.mnemonics.is_some() && !self.error.is_none()
+    }
+
+    /// Another handle on the unlocked mnemonic, wiping it also wipes the
+    /// one held by this result.
+    #[frb(sync)]
+    pub fn result(&self) -> Option<Mnemonic> {
+        self.mnemonics.as_ref().map(|m| m.share())
+    }
+
+    #[frb(sync)]
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, value: Mnemonic) {
+        self.mnemonics = Some(value);
+    }
+
+    #[frb(sync)]
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_path;
+
+    const PHRASE: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("vault.json").display().to_string();
+        let mnemonics = Mnemonic::from_string(PHRASE.to_string()).unwrap();
+        assert!(create_vault(path.clone(), &mnemonics, "password".to_string()).is_ok());
+        assert!(vault_exists(path.clone()));
+
+        let unlocked = unlock_vault(path.clone(), "password".to_string());
+        assert_eq!(
+            unlocked.result().unwrap().phrase().unwrap().as_str(),
+            PHRASE
+        );
+
+        let wrong = unlock_vault(path.clone(), "wrong".to_string());
+        assert!(wrong.is_err());
+        assert_eq!(
+            wrong.error().as_deref(),
+            Some("wrong password or corrupted vault")
+        );
+
+        let res = change_vault_password(path.clone(), "password".to_string(), "new".to_string());
+        assert!(res.is_ok());
+        assert!(unlock_vault(path.clone(), "password".to_string()).is_err());
+        assert!(unlock_vault(path.clone(), "new".to_string()).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tampered_header_refused() {
+        let mnemonics = Mnemonic::from_string(PHRASE.to_string()).unwrap();
+        let mut vault = seal(&mnemonics, "password").unwrap();
+        let key = vault.key("password").unwrap();
+        assert!(decrypt(&vault, &key).is_ok());
+
+        // the header changes the key, with the key of the sealed header the
+        // authentication of the header alone refuses it
+        vault.t_cost += 1;
+        let error = decrypt(&vault, &key).err();
+        assert_eq!(error.as_deref(), Some("wrong password or corrupted vault"));
+    }
+
+    #[test]
+    fn expensive_kdf_refused() {
+        let mnemonics = Mnemonic::from_string(PHRASE.to_string()).unwrap();
+        let mut vault = seal(&mnemonics, "password").unwrap();
+        vault.m_cost = MAX_M_COST + 1;
+        let error = open(&vault, "password").err();
+        assert_eq!(error.as_deref(), Some("vault key derivation too expensive"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_only_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("vault.json");
+        let mnemonics = Mnemonic::from_string(PHRASE.to_string()).unwrap();
+        let res = create_vault(
+            path.display().to_string(),
+            &mnemonics,
+            "password".to_string(),
+        );
+        assert!(res.is_ok());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(path).unwrap();
+    }
+}
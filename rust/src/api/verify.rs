@@ -0,0 +1,316 @@
+use std::sync::Mutex;
+
+use joinstr::{
+    miniscript::bitcoin::{Amount, OutPoint, Psbt, ScriptBuf},
+    signer::{self, JoinstrSigner},
+};
+
+use super::joinstr::{Address, Coin, Pool};
+
+/// Virtual size of a P2WPKH witness (signature + pubkey), the unsigned
+/// transaction does not account for it.
+pub(crate) const WPKH_WITNESS_VSIZE: u64 = 27;
+
+/// What we expect to find in the coinjoin transaction before signing it.
+#[derive(Debug, Clone)]
+pub(crate) struct Expected {
+    pub input: OutPoint,
+    pub input_script: ScriptBuf,
+    pub output_script: ScriptBuf,
+    pub denomination: Amount,
+    pub peers: usize,
+    /// Maximum fee rate in sat/vb
+    pub fee: u32,
+}
+
+impl Expected {
+    pub fn new(pool: &Pool, input: &Coin, output: &Address) -> Result<Self, String> {
+        Ok(Expected {
+            input: input.outpoint_inner(),
+            input_script: input.script_pubkey(),
+            output_script: output.as_unchecked().assume_checked_ref().script_pubkey(),
+            denomination: Amount::from_sat(
+                pool.denomination_sat()
+                    .ok_or("pool without denomination".to_string())?,
+            ),
+            peers: pool.peers().ok_or("pool without peers".to_string())?,
+            fee: pool.fee().ok_or("pool without fixed fee".to_string())?,
+        })
+    }
+}
+
+/// Checks run on the coinjoin transaction before we sign our input.
+#[derive(Debug, Clone)]
+pub struct VerificationReport {
+    pub input_present: bool,
+    pub output_present: bool,
+    pub equal_outputs: usize,
+    pub peers_match: bool,
+    /// Total fee of the transaction, None if an input misses its utxo.
+    pub fee_sat: Option<u64>,
+    /// Estimated fee rate of the signed transaction in sat/vb.
+    pub fee_rate: Option<f64>,
+    pub fee_ok: bool,
+    pub no_unexpected_outputs: bool,
+    /// Human readable description of the failed checks.
+    pub errors: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub(crate) fn verify(psbt: &Psbt, expected: &Expected) -> VerificationReport {
+    let tx = &psbt.unsigned_tx;
+    let mut errors = Vec::new();
+
+    let input_present = tx.input.iter().any(|i| i.previous_output == expected.input);
+    if !input_present {
+        errors.push("our input is missing".to_string());
+    }
+
+    let output_present = tx
+        .output
+        .iter()
+        .any(|o| o.script_pubkey == expected.output_script && o.value == expected.denomination);
+    if !output_present {
+        errors.push("our output is missing or has a wrong amount".to_string());
+    }
+
+    let equal_outputs = tx
+        .output
+        .iter()
+        .filter(|o| o.value == expected.denomination)
+        .count();
+    let peers_match = equal_outputs == expected.peers && tx.output.len() == expected.peers;
+    if !peers_match {
+        errors.push(format!(
+            "expected {} outputs of {}, found {equal_outputs} over {} outputs",
+            expected.peers,
+            expected.denomination,
+            tx.output.len()
+        ));
+    }
+
+    let inputs: Option<u64> = psbt
+        .inputs
+        .iter()
+        .map(|i| i.witness_utxo.as_ref().map(|u| u.value.to_sat()))
+        .sum();
+    let outputs: u64 = tx.output.iter().map(|o| o.value.to_sat()).sum();
+    let fee_sat = inputs.and_then(|i| i.checked_sub(outputs));
+    let vsize = tx.vsize() as u64 + WPKH_WITNESS_VSIZE * tx.input.len() as u64;
+    let fee_rate = fee_sat.map(|f| f as f64 / vsize as f64);
+    let fee_ok = fee_rate.map(|r| r <= expected.fee as f64).unwrap_or(false);
+    match fee_rate {
+        Some(rate) if !fee_ok => errors.push(format!(
+            "fee rate {rate:.2} sat/vb above pool fee {} sat/vb",
+            expected.fee
+        )),
+        None => errors.push("cannot compute the transaction fee".to_string()),
+        _ => {}
+    }
+
+    let ours = tx
+        .output
+        .iter()
+        .filter(|o| o.script_pubkey == expected.output_script)
+        .count();
+    let to_input = tx
+        .output
+        .iter()
+        .any(|o| o.script_pubkey == expected.input_script);
+    let no_unexpected_outputs = ours <= 1 && !to_input;
+    if !no_unexpected_outputs {
+        errors.push("unexpected output paying to our scripts".to_string());
+    }
+
+    VerificationReport {
+        input_present,
+        output_present,
+        equal_outputs,
+        peers_match,
+        fee_sat,
+        fee_rate,
+        fee_ok,
+        no_unexpected_outputs,
+        errors,
+    }
+}
+
+/// Wrap a signer so it refuses to sign a transaction failing the checks,
+/// unless `force` is set. The last report is kept for the caller.
+pub(crate) struct VerifyingSigner<S> {
+    pub inner: S,
+    pub expected: Expected,
+    pub force: bool,
+    pub report: Mutex<Option<VerificationReport>>,
+}
+
+impl<S: JoinstrSigner> JoinstrSigner for VerifyingSigner<S> {
+    fn sign_input(&self, psbt: &mut Psbt, input_index: usize) -> Result<(), signer::Error> {
+        let report = verify(psbt, &self.expected);
+        let passed = report.passed();
+        let errors = report.errors.join(", ");
+        *self.report.lock().expect("poisoned") = Some(report);
+        if !passed {
+            if !self.force {
+                return Err(signer::Error::Signer(errors));
+            }
+            log::warn!("sign a coinjoin failing verification: {errors}");
+        }
+        self.inner.sign_input(psbt, input_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, TxOut, Txid};
+
+    use super::*;
+    use crate::test_support::{script, transaction};
+
+    const DENOMINATION: u64 = 100_000;
+
+    fn ours() -> OutPoint {
+        OutPoint::new(Txid::all_zeros(), 0)
+    }
+
+    fn expected(fee: u32) -> Expected {
+        Expected {
+            input: ours(),
+            input_script: script(1),
+            output_script: script(2),
+            denomination: Amount::from_sat(DENOMINATION),
+            peers: 2,
+            fee,
+        }
+    }
+
+    /// Coinjoin of 2 peers, each input brings `input_sat`.
+    fn coinjoin(outputs: &[(ScriptBuf, u64)], input_sat: u64) -> Psbt {
+        let other = OutPoint::new(Txid::all_zeros(), 1);
+        let tx = transaction(&[ours(), other], outputs);
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, script) in psbt.inputs.iter_mut().zip([script(1), script(3)]) {
+            input.witness_utxo = Some(TxOut {
+                value: Amount::from_sat(input_sat),
+                script_pubkey: script,
+            });
+        }
+        psbt
+    }
+
+    #[test]
+    fn valid_coinjoin() {
+        let psbt = coinjoin(
+            &[(script(2), DENOMINATION), (script(4), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        let report = verify(&psbt, &expected(2));
+        assert!(report.passed(), "{:?}", report.errors);
+        assert_eq!(report.fee_sat, Some(400));
+        assert_eq!(report.equal_outputs, 2);
+    }
+
+    #[test]
+    fn fee_above_pool_fee() {
+        let psbt = coinjoin(
+            &[(script(2), DENOMINATION), (script(4), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        let report = verify(&psbt, &expected(1));
+        assert!(!report.fee_ok);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn missing_output() {
+        let psbt = coinjoin(
+            &[(script(4), DENOMINATION), (script(5), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        let report = verify(&psbt, &expected(2));
+        assert!(!report.output_present);
+        assert!(report.input_present);
+        assert_eq!(report.errors.len(), 1);
+    }
+
+    #[test]
+    fn unexpected_outputs() {
+        // an extra output sending part of the funds back to our input script
+        let psbt = coinjoin(
+            &[
+                (script(2), DENOMINATION),
+                (script(4), DENOMINATION),
+                (script(1), 100),
+            ],
+            DENOMINATION + 300,
+        );
+        let report = verify(&psbt, &expected(2));
+        assert!(!report.peers_match);
+        assert!(!report.no_unexpected_outputs);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn missing_utxo() {
+        let mut psbt = coinjoin(
+            &[(script(2), DENOMINATION), (script(4), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        psbt.inputs[1].witness_utxo = None;
+        let report = verify(&psbt, &expected(2));
+        assert_eq!(report.fee_sat, None);
+        assert!(!report.passed());
+    }
+
+    /// Counts the inputs it is asked to sign.
+    #[derive(Default)]
+    struct Counter(Mutex<usize>);
+
+    impl JoinstrSigner for Counter {
+        fn sign_input(&self, _: &mut Psbt, _: usize) -> Result<(), signer::Error> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    fn verifying(force: bool) -> VerifyingSigner<Counter> {
+        VerifyingSigner {
+            inner: Counter::default(),
+            expected: expected(2),
+            force,
+            report: Mutex::new(None),
+        }
+    }
+
+    #[test]
+    fn failed_coinjoin_not_signed() {
+        let mut psbt = coinjoin(
+            &[(script(4), DENOMINATION), (script(5), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        let signer = verifying(false);
+        assert!(signer.sign_input(&mut psbt, 0).is_err());
+        assert_eq!(*signer.inner.0.lock().unwrap(), 0);
+        let report = signer.report.lock().unwrap().clone().unwrap();
+        assert!(!report.output_present);
+
+        // forced, the same coinjoin is signed and the report kept
+        let signer = verifying(true);
+        assert!(signer.sign_input(&mut psbt, 0).is_ok());
+        assert_eq!(*signer.inner.0.lock().unwrap(), 1);
+        assert!(!signer.report.lock().unwrap().as_ref().unwrap().passed());
+
+        let mut psbt = coinjoin(
+            &[(script(2), DENOMINATION), (script(4), DENOMINATION)],
+            DENOMINATION + 200,
+        );
+        let signer = verifying(false);
+        assert!(signer.sign_input(&mut psbt, 0).is_ok());
+        assert_eq!(*signer.inner.0.lock().unwrap(), 1);
+    }
+}
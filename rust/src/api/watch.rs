@@ -0,0 +1,183 @@
+use std::{thread, time::Duration};
+
+use joinstr::miniscript::bitcoin::{ScriptBuf, Txid};
+
+use super::electrum;
+use crate::frb_generated::StreamSink;
+
+/// Longest wait between two polls of the electrum server, a status is sent
+/// at least this often so we notice when the app stopped listening.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxState {
+    /// The server does not know the transaction (yet).
+    NotFound,
+    Mempool,
+    Confirmed,
+    /// The transaction was seen then disappeared, e.g. double spent.
+    Dropped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxStatus {
+    pub state: TxState,
+    pub confirmations: u32,
+    pub height: Option<u32>,
+}
+
+/// Poll the server for the status of `txid`, we need one of its output
+/// scripts to look it up in the script history.
+fn poll(
+    client: &mut electrum::Client,
+    txid: &Txid,
+    script: &mut Option<ScriptBuf>,
+    seen: bool,
+) -> Result<TxStatus, String> {
+    let not_found = TxStatus {
+        state: if seen {
+            TxState::Dropped
+        } else {
+            TxState::NotFound
+        },
+        confirmations: 0,
+        height: None,
+    };
+    if script.is_none() {
+        match client.get_transaction(txid) {
+            Ok(tx) => *script = tx.output.first().map(|o| o.script_pubkey.clone()),
+            Err(_) => return Ok(not_found),
+        }
+    }
+    let Some(script) = script else {
+        return Err("transaction without output".to_string());
+    };
+
+    let height = client
+        .script_history(script)?
+        .into_iter()
+        .find(|(t, _)| t == txid)
+        .map(|(_, h)| h);
+    match height {
+        None => Ok(not_found),
+        Some(h) if h <= 0 => Ok(TxStatus {
+            state: TxState::Mempool,
+            confirmations: 0,
+            height: None,
+        }),
+        Some(h) => {
+            let tip = client.tip_height()?;
+            Ok(TxStatus {
+                state: TxState::Confirmed,
+                confirmations: tip.saturating_sub(h as u32) + 1,
+                height: Some(h as u32),
+            })
+        }
+    }
+}
+
+/// Wait for the next change of the transaction: we subscribe to its output
+/// script once known, and to new blocks once it is confirmed (see
+/// [`electrum::Client::tip_height`]).
+fn wait_change(
+    client: &mut electrum::Client,
+    script: &Option<ScriptBuf>,
+    subscribed: &mut bool,
+) -> Result<(), String> {
+    if let (Some(script), false) = (script, *subscribed) {
+        client.subscribe_script(script)?;
+        *subscribed = true;
+    }
+    client.wait_notification(POLL_INTERVAL)?;
+    Ok(())
+}
+
+/// Follow `txid` until it reaches `confirmations` confirmations or is
+/// dropped. A status is sent each time the server notifies a change and at
+/// least every [`POLL_INTERVAL`], the watch stops once the app no longer
+/// listens.
+pub fn watch_transaction(
+    txid: String,
+    electrum_url: String,
+    electrum_port: u16,
+    confirmations: u32,
+    sink: StreamSink<TxStatus>,
+) -> Result<(), String> {
+    let txid: Txid = txid.parse().map_err(|e| format!("{e}"))?;
+    let mut client = electrum::Client::new(&electrum_url, electrum_port)?;
+    let mut script = None;
+    let mut subscribed = false;
+    let mut last: Option<TxStatus> = None;
+
+    loop {
+        let seen = last
+            .as_ref()
+            .map(|s| matches!(s.state, TxState::Mempool | TxState::Confirmed))
+            .unwrap_or(false);
+        let status = match poll(&mut client, &txid, &mut script, seen) {
+            Ok(s) => s,
+            Err(e) => {
+                // reconnect once, the server may have dropped the connection
+                log::warn!("watch {txid}: {e}");
+                client = electrum::Client::new(&electrum_url, electrum_port)?;
+                subscribed = false;
+                poll(&mut client, &txid, &mut script, seen)?
+            }
+        };
+        // sent even if unchanged, a failed send is how we learn that the
+        // app stopped listening
+        if sink.add(status.clone()).is_err() {
+            return Ok(());
+        }
+        let done = status.state == TxState::Dropped
+            || (status.state == TxState::Confirmed && status.confirmations >= confirmations);
+        if done {
+            return Ok(());
+        }
+        last = Some(status);
+        if let Err(e) = wait_change(&mut client, &script, &mut subscribed) {
+            // the next poll reconnects
+            log::warn!("watch {txid}: {e}");
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use joinstr::miniscript::bitcoin::{hashes::Hash, OutPoint};
+
+    use super::*;
+    use crate::test_support::{script, transaction, MockElectrum};
+
+    #[test]
+    fn poll_states() {
+        let electrum = MockElectrum::start();
+        let mut client = electrum::Client::new(&electrum.url(), electrum.port()).unwrap();
+        let tx = transaction(
+            &[OutPoint::new(Txid::all_zeros(), 0)],
+            &[(script(1), 1_000)],
+        );
+        let txid = tx.compute_txid();
+        let mut output = None;
+        let status = poll(&mut client, &txid, &mut output, false).unwrap();
+        assert_eq!(status.state, TxState::NotFound);
+        let status = poll(&mut client, &txid, &mut output, true).unwrap();
+        assert_eq!(status.state, TxState::Dropped);
+        assert_eq!(output, None);
+
+        electrum.add_mempool(tx.clone());
+        let status = poll(&mut client, &txid, &mut output, false).unwrap();
+        assert_eq!(status.state, TxState::Mempool);
+        assert_eq!(status.height, None);
+        // the script is looked up once
+        assert_eq!(output, Some(script(1)));
+
+        electrum.confirm(tx);
+        electrum.mine(2);
+        let status = poll(&mut client, &txid, &mut output, true).unwrap();
+        assert_eq!(status.state, TxState::Confirmed);
+        assert_eq!(status.height, Some(1));
+        assert_eq!(status.confirmations, 3);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod api;
 mod frb_generated;
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,472 @@
+//! Helpers for the offline tests: an in-process Electrum server serving a
+//! fake chain held in memory.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use joinstr::miniscript::bitcoin::{
+    absolute::LockTime,
+    block::{self, Header},
+    consensus::encode::{deserialize_hex, serialize_hex},
+    constants::genesis_block,
+    hashes::{sha256, Hash},
+    secp256k1::{Secp256k1, SecretKey},
+    transaction::Version,
+    Amount, CompactTarget, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode,
+    TxOut, Txid, WPubkeyHash, Witness,
+};
+use joinstr::{
+    nostr,
+    signer::{self, CoinPath},
+};
+use serde_json::{json, Value};
+
+use crate::api::{
+    electrum::script_hash,
+    joinstr::{Coin, Mnemonic, Pool},
+    store,
+};
+
+/// A `blockchain.scripthash.subscribe` of a client connection.
+struct Subscription {
+    hash: String,
+    status: Option<String>,
+    client: Arc<Mutex<TcpStream>>,
+}
+
+#[derive(Default)]
+struct Chain {
+    headers: Vec<Header>,
+    /// txid => (transaction, height), a height of 0 means in the mempool
+    transactions: BTreeMap<Txid, (Transaction, i64)>,
+    /// Fee estimate returned for every target, in BTC/kvb
+    fee_estimate: Option<f64>,
+    relay_fee: f64,
+    broadcasted: Vec<Transaction>,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Chain {
+    fn tip(&self) -> (usize, &Header) {
+        let height = self.headers.len() - 1;
+        (height, &self.headers[height])
+    }
+
+    fn spent(&self, outpoint: &OutPoint) -> bool {
+        self.transactions
+            .values()
+            .any(|(tx, _)| tx.input.iter().any(|i| i.previous_output == *outpoint))
+    }
+
+    /// Transactions paying to or spending from `hash`.
+    fn history(&self, hash: &str) -> Vec<(&Transaction, i64)> {
+        let pays = |tx: &Transaction| {
+            tx.output
+                .iter()
+                .any(|o| script_hash(&o.script_pubkey) == hash)
+        };
+        let spends = |tx: &Transaction| {
+            tx.input.iter().any(|i| {
+                self.transactions
+                    .get(&i.previous_output.txid)
+                    .and_then(|(prev, _)| prev.output.get(i.previous_output.vout as usize))
+                    .map(|o| script_hash(&o.script_pubkey) == hash)
+                    .unwrap_or(false)
+            })
+        };
+        self.transactions
+            .values()
+            .filter(|(tx, _)| pays(tx) || spends(tx))
+            .map(|(tx, height)| (tx, *height))
+            .collect()
+    }
+
+    /// Status of a script as defined by the electrum protocol, None if it
+    /// has no history.
+    fn status(&self, hash: &str) -> Option<String> {
+        let history = self.history(hash);
+        if history.is_empty() {
+            return None;
+        }
+        let status: String = history
+            .iter()
+            .map(|(tx, height)| format!("{}:{height}:", tx.compute_txid()))
+            .collect();
+        Some(sha256::Hash::hash(status.as_bytes()).to_string())
+    }
+
+    /// Notify the subscribers of the scripts whose status changed.
+    fn notify(&mut self) {
+        let statuses: Vec<_> = self
+            .subscriptions
+            .iter()
+            .map(|s| self.status(&s.hash))
+            .collect();
+        for (sub, status) in self.subscriptions.iter_mut().zip(statuses) {
+            if sub.status == status {
+                continue;
+            }
+            sub.status = status;
+            let notification = json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.scripthash.subscribe",
+                "params": [sub.hash, sub.status],
+            });
+            let _ = writeln!(sub.client.lock().expect("poisoned"), "{notification}");
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        params: &Value,
+        client: &Arc<Mutex<TcpStream>>,
+    ) -> Result<Value, String> {
+        let hash = params
+            .get(0)
+            .and_then(Value::as_str)
+            .ok_or("invalid script hash")?
+            .to_string();
+        let status = self.status(&hash);
+        self.subscriptions.push(Subscription {
+            hash,
+            status: status.clone(),
+            client: client.clone(),
+        });
+        Ok(json!(status))
+    }
+
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, String> {
+        let param = |i: usize| params.get(i).cloned().unwrap_or(Value::Null);
+        match method {
+            "server.version" => Ok(json!(["mock-electrum 0.1", "1.4"])),
+            "server.ping" => Ok(Value::Null),
+            "blockchain.headers.subscribe" => {
+                let (height, header) = self.tip();
+                Ok(json!({"height": height, "hex": serialize_hex(header)}))
+            }
+            "blockchain.block.header" => {
+                let height = param(0).as_u64().ok_or("invalid height")? as usize;
+                let header = self.headers.get(height).ok_or("height above tip")?;
+                Ok(json!(serialize_hex(header)))
+            }
+            "blockchain.estimatefee" => Ok(json!(self.fee_estimate.unwrap_or(-1.0))),
+            "blockchain.relayfee" => Ok(json!(self.relay_fee)),
+            "blockchain.transaction.get" => {
+                let txid: Txid = param(0)
+                    .as_str()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or("invalid txid")?;
+                let (tx, _) = self
+                    .transactions
+                    .get(&txid)
+                    .ok_or("transaction not found")?;
+                Ok(json!(serialize_hex(tx)))
+            }
+            "blockchain.transaction.broadcast" => {
+                let tx: Transaction = param(0)
+                    .as_str()
+                    .and_then(|raw| deserialize_hex(raw).ok())
+                    .ok_or("invalid transaction")?;
+                let txid = tx.compute_txid();
+                self.broadcasted.push(tx.clone());
+                self.transactions.insert(txid, (tx, 0));
+                self.notify();
+                Ok(json!(txid.to_string()))
+            }
+            "blockchain.scripthash.get_history" => {
+                let hash = param(0);
+                let hash = hash.as_str().ok_or("invalid script hash")?;
+                let history: Vec<_> = self
+                    .history(hash)
+                    .into_iter()
+                    .map(|(tx, height)| {
+                        json!({"tx_hash": tx.compute_txid().to_string(), "height": height})
+                    })
+                    .collect();
+                Ok(json!(history))
+            }
+            "blockchain.scripthash.listunspent" => {
+                let hash = param(0);
+                let hash = hash.as_str().ok_or("invalid script hash")?;
+                let mut unspent = Vec::new();
+                for (tx, height) in self.history(hash) {
+                    let txid = tx.compute_txid();
+                    for (vout, output) in tx.output.iter().enumerate() {
+                        let outpoint = OutPoint::new(txid, vout as u32);
+                        if script_hash(&output.script_pubkey) == hash && !self.spent(&outpoint) {
+                            unspent.push(json!({
+                                "tx_hash": txid.to_string(),
+                                "tx_pos": vout,
+                                "height": height,
+                                "value": output.value.to_sat(),
+                            }));
+                        }
+                    }
+                }
+                Ok(json!(unspent))
+            }
+            _ => Err(format!("unknown method {method}")),
+        }
+    }
+}
+
+/// Electrum server listening on localhost, the chain starts at the regtest
+/// genesis block.
+pub(crate) struct MockElectrum {
+    port: u16,
+    chain: Arc<Mutex<Chain>>,
+}
+
+/// Answer a single JSON-RPC request.
+fn respond(request: &Value, chain: &Mutex<Chain>, writer: &Arc<Mutex<TcpStream>>) -> Value {
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = request.get("params").cloned().unwrap_or(json!([]));
+    let result = match method {
+        "blockchain.scripthash.subscribe" => {
+            chain.lock().expect("poisoned").subscribe(&params, writer)
+        }
+        _ => chain.lock().expect("poisoned").handle(method, &params),
+    };
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+        Err(e) => json!({"jsonrpc": "2.0", "id": request["id"], "error": {"message": e}}),
+    }
+}
+
+fn serve(stream: TcpStream, chain: Arc<Mutex<Chain>>) {
+    let writer = match stream.try_clone() {
+        Ok(w) => Arc::new(Mutex::new(w)),
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            return;
+        };
+        // a batch is answered with the array of its responses
+        let response = match &request {
+            Value::Array(batch) => batch.iter().map(|r| respond(r, &chain, &writer)).collect(),
+            _ => respond(&request, &chain, &writer),
+        };
+        if writeln!(writer.lock().expect("poisoned"), "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+impl MockElectrum {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let port = listener.local_addr().expect("local addr").port();
+        let chain = Arc::new(Mutex::new(Chain {
+            headers: vec![genesis_block(Network::Regtest).header],
+            relay_fee: 0.00001,
+            ..Default::default()
+        }));
+        let shared = chain.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let chain = shared.clone();
+                thread::spawn(move || serve(stream, chain));
+            }
+        });
+        MockElectrum { port, chain }
+    }
+
+    pub fn url(&self) -> String {
+        "127.0.0.1".to_string()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Extend the chain by `count` empty blocks.
+    pub fn mine(&self, count: usize) {
+        let mut chain = self.chain.lock().expect("poisoned");
+        for _ in 0..count {
+            let (_, tip) = chain.tip();
+            let header = Header {
+                version: block::Version::TWO,
+                prev_blockhash: tip.block_hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: tip.time + 600,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            };
+            chain.headers.push(header);
+        }
+    }
+
+    /// Add `tx` to the mempool.
+    pub fn add_mempool(&self, tx: Transaction) -> Txid {
+        let txid = tx.compute_txid();
+        let mut chain = self.chain.lock().expect("poisoned");
+        chain.transactions.insert(txid, (tx, 0));
+        chain.notify();
+        txid
+    }
+
+    /// Mine a block confirming `tx`.
+    pub fn confirm(&self, tx: Transaction) -> Txid {
+        self.mine(1);
+        let txid = tx.compute_txid();
+        let mut chain = self.chain.lock().expect("poisoned");
+        let height = chain.tip().0 as i64;
+        chain.transactions.insert(txid, (tx, height));
+        chain.notify();
+        txid
+    }
+
+    /// Fee estimate in sat/vb returned for every target, None if the server
+    /// cannot estimate.
+    pub fn set_fee_estimate(&self, rate: Option<f64>) {
+        self.chain.lock().expect("poisoned").fee_estimate =
+            rate.map(|r| r * 1_000.0 / 100_000_000.0);
+    }
+
+    pub fn broadcasted(&self) -> Vec<Transaction> {
+        self.chain.lock().expect("poisoned").broadcasted.clone()
+    }
+}
+
+/// Transaction spending `inputs` to `outputs`, it is not signed.
+pub(crate) fn transaction(inputs: &[OutPoint], outputs: &[(ScriptBuf, u64)]) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|(script, value)| TxOut {
+                value: Amount::from_sat(*value),
+                script_pubkey: script.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// The BIP39 test mnemonic `abandon abandon ... about`.
+pub(crate) fn mnemonic() -> Mnemonic {
+    let phrase = ["abandon"; 11].join(" ") + " about";
+    Mnemonic::from_string(phrase).expect("valid mnemonic")
+}
+
+/// A P2WPKH script unique to `seed`.
+pub(crate) fn script(seed: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([seed; 20]))
+}
+
+/// A wallet coin spending `outpoint`.
+pub(crate) fn coin(outpoint: OutPoint, script: ScriptBuf, value: u64) -> Coin {
+    signer::Coin {
+        txout: TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script,
+        },
+        outpoint,
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        coin_path: CoinPath {
+            depth: 0,
+            index: Some(0),
+        },
+    }
+    .into()
+}
+
+/// A path in the temp directory no other test uses, nothing is created.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("dart_joinstr-{}-{n}-{name}", std::process::id()))
+}
+
+/// The local state of a test, the directory is removed on drop.
+pub(crate) struct DataDir {
+    path: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Point the local state to a fresh directory, tests using the state hold
+/// the returned guard so they do not run concurrently.
+pub(crate) fn data_dir() -> DataDir {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = temp_path("state");
+    assert!(store::set_data_dir(path.display().to_string()).is_ok());
+    DataDir { path, _lock: lock }
+}
+
+/// A regtest pool with a fixed fee, `timeout` is a unix timestamp.
+pub(crate) fn pool(id: &str, denomination: u64, peers: usize, fee: u32, timeout: u64) -> Pool {
+    let key = SecretKey::from_slice(&[1; 32]).expect("valid key");
+    nostr::Pool {
+        id: id.to_string(),
+        public_key: key.x_only_public_key(&Secp256k1::new()).0,
+        network: Network::Regtest,
+        payload: Some(nostr::PoolPayload {
+            denomination: Amount::from_sat(denomination),
+            peers,
+            relays: vec!["wss://relay.example".to_string()],
+            fee: nostr::Fee::Fixed(fee),
+            timeout,
+        }),
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_requests() {
+        let electrum = MockElectrum::start();
+        let txid = electrum.confirm(transaction(&[OutPoint::null()], &[(script(1), 1_000)]));
+        let stream = TcpStream::connect(("127.0.0.1", electrum.port())).unwrap();
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": 0, "method": "blockchain.scripthash.get_history",
+                "params": [script_hash(&script(1))]},
+            {"jsonrpc": "2.0", "id": 1, "method": "server.unknown", "params": []},
+        ]);
+        writeln!(&stream, "{batch}").unwrap();
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).unwrap();
+
+        let responses: Value = serde_json::from_str(&line).unwrap();
+        let responses = responses.as_array().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 0);
+        assert_eq!(
+            responses[0]["result"],
+            json!([{"tx_hash": txid.to_string(), "height": 1}])
+        );
+        assert_eq!(responses[1]["id"], 1);
+        assert!(responses[1]["error"].is_object());
+    }
+}